@@ -0,0 +1,104 @@
+use sqlite_loadable::{
+    api,
+    ext::{sqlite3_api_routines, sqlite3ext_create_function_v2},
+    prelude::*,
+    Error, Result, SQLITE_OKAY,
+};
+
+use std::{
+    ffi::CString,
+    mem,
+    os::raw::{c_int, c_void},
+    ptr, slice,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// The running state of one call of an aggregate function, created on its first row.
+pub(crate) trait Aggregate: Default {
+    fn step(&mut self, values: &[*mut sqlite3_value]) -> Result<()>;
+    fn finalize(self, context: *mut sqlite3_context) -> Result<()>;
+}
+
+/// sqlite-loadable doesn't wrap aggregate functions, and keeps the API routines it's
+/// loaded with private, so the extension's entrypoint stores its own copy of them here
+/// for `sqlite3_aggregate_context()`.
+static SQLITE3_API: AtomicPtr<sqlite3_api_routines> = AtomicPtr::new(ptr::null_mut());
+
+pub(crate) fn set_api_routines(p_api: *mut sqlite3_api_routines) {
+    SQLITE3_API.store(p_api, Ordering::Relaxed);
+}
+
+/// The slot for this aggregate call's state, or `None` when no rows were stepped and
+/// `allocate` is false.
+unsafe fn state_slot<A>(context: *mut sqlite3_context, allocate: bool) -> Option<*mut *mut A> {
+    let api = SQLITE3_API.load(Ordering::Relaxed);
+    let aggregate_context = api.as_ref()?.aggregate_context?;
+    let size = if allocate {
+        mem::size_of::<*mut A>() as c_int
+    } else {
+        0
+    };
+    // SQLite zeroes the memory on the first call, so the slot starts out null
+    let slot = aggregate_context(context, size) as *mut *mut A;
+    (!slot.is_null()).then_some(slot)
+}
+
+fn result_error(context: *mut sqlite3_context, result: Result<()>) {
+    if let Err(err) = result {
+        api::result_error(context, &err.result_error_message()).ok();
+    }
+}
+
+unsafe extern "C" fn x_step<A: Aggregate>(
+    context: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let values = slice::from_raw_parts(argv, argc as usize);
+    let result = match state_slot::<A>(context, true) {
+        Some(slot) => {
+            if (*slot).is_null() {
+                *slot = Box::into_raw(Box::default());
+            }
+            (**slot).step(values)
+        }
+        None => Err(Error::new_message("out of memory for aggregate state")),
+    };
+    result_error(context, result);
+}
+
+unsafe extern "C" fn x_final<A: Aggregate>(context: *mut sqlite3_context) {
+    // aggregating no rows finalizes a fresh state
+    let state = match state_slot::<A>(context, false) {
+        Some(slot) if !(*slot).is_null() => *Box::from_raw(*slot),
+        _ => A::default(),
+    };
+    result_error(context, state.finalize(context));
+}
+
+/// Registers an aggregate function whose state is an `A`.
+pub(crate) fn define_aggregate_function<A: Aggregate>(
+    db: *mut sqlite3,
+    name: &str,
+    num_args: c_int,
+    func_flags: FunctionFlags,
+) -> Result<()> {
+    let c_name = CString::new(name)?;
+    let result = unsafe {
+        sqlite3ext_create_function_v2(
+            db,
+            c_name.as_ptr(),
+            num_args,
+            (FunctionFlags::UTF8 | func_flags).bits(),
+            ptr::null_mut::<c_void>(),
+            None,
+            Some(x_step::<A>),
+            Some(x_final::<A>),
+            None,
+        )
+    };
+    if result != SQLITE_OKAY {
+        return Err(Error::new_message(format!("could not define {name}()")));
+    }
+    Ok(())
+}
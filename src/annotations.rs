@@ -1,21 +1,27 @@
 use pdfium_render::{
     document::PdfDocument,
+    page::PdfPage,
     page_annotation::{PdfPageAnnotation, PdfPageAnnotationCommon, PdfPageAnnotationType},
     page_annotations::PdfPageAnnotationsIterator,
+    page_text::PdfPageText,
+    rect::PdfRect,
 };
+use serde_json::{json, Value};
 use sqlite_loadable::prelude::*;
 use sqlite_loadable::{
     api,
     table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
-    BestIndexError, Result,
+    BestIndexError, Error, Result,
 };
 
 use std::{marker::PhantomData, mem, os::raw::c_int};
 
-use crate::PagePointer;
+use crate::{
+    attachments::{annotation_file, EmbeddedFile},
+    color_hex, page_raw, pdfium_error, value_page, PagePointer,
+};
 
-static CREATE_SQL: &str =
-    "CREATE TABLE x(type, x, y, width, height, name, contents, creator, created_at, modified_at, page hidden)";
+static CREATE_SQL: &str = "CREATE TABLE x(type, x, y, width, height, name, contents, creator, created_at, modified_at, fill_color text, stroke_color text, opacity, flags int, hidden int, print int, locked int, quad_points, ink_paths, line, vertices, popup_id int, marked_text text, attachment_name text, attachment blob, page hidden)";
 enum Columns {
     Type,
     X,
@@ -27,6 +33,21 @@ enum Columns {
     Creator,
     CreatedAt,
     ModifiedAt,
+    FillColor,
+    StrokeColor,
+    Opacity,
+    Flags,
+    Hidden,
+    Print,
+    Locked,
+    QuadPoints,
+    InkPaths,
+    Line,
+    Vertices,
+    PopupId,
+    MarkedText,
+    AttachmentName,
+    Attachment,
     Page,
 }
 fn column(index: i32) -> Option<Columns> {
@@ -41,11 +62,145 @@ fn column(index: i32) -> Option<Columns> {
         7 => Some(Columns::Creator),
         8 => Some(Columns::CreatedAt),
         9 => Some(Columns::ModifiedAt),
-        10 => Some(Columns::Page),
+        10 => Some(Columns::FillColor),
+        11 => Some(Columns::StrokeColor),
+        12 => Some(Columns::Opacity),
+        13 => Some(Columns::Flags),
+        14 => Some(Columns::Hidden),
+        15 => Some(Columns::Print),
+        16 => Some(Columns::Locked),
+        17 => Some(Columns::QuadPoints),
+        18 => Some(Columns::InkPaths),
+        19 => Some(Columns::Line),
+        20 => Some(Columns::Vertices),
+        21 => Some(Columns::PopupId),
+        22 => Some(Columns::MarkedText),
+        23 => Some(Columns::AttachmentName),
+        24 => Some(Columns::Attachment),
+        25 => Some(Columns::Page),
         _ => None,
     }
 }
 
+// annotation flags, from section 12.5.3 of the PDF 1.7 spec
+const FLAG_HIDDEN: i32 = 1 << 1;
+const FLAG_PRINT: i32 = 1 << 2;
+const FLAG_LOCKED: i32 = 1 << 7;
+
+/// Annotation properties that pdfium-render doesn't wrap, read straight from pdfium.
+struct RawProperties {
+    opacity: f32,
+    flags: i32,
+    ink_paths: Vec<Vec<[f32; 2]>>,
+    line: Option<[[f32; 2]; 2]>,
+    vertices: Vec<[f32; 2]>,
+    /// Index of the annotation's popup on the same page
+    popup_index: Option<i32>,
+}
+
+impl RawProperties {
+    /// Reads the properties of the annotation at `index` on the page. The pdfium handle
+    /// types aren't exported by pdfium-render, so this has to stay in a single function
+    /// where they can be inferred.
+    fn read(page: &PdfPage, index: usize) -> Option<RawProperties> {
+        let bindings = page.bindings();
+        let page_handle = bindings.get_handle_from_page(page);
+        let handle = bindings.FPDFPage_GetAnnot(page_handle, index as c_int);
+        if handle.is_null() {
+            return None;
+        }
+
+        let mut opacity = 1.0;
+        if !bindings.is_true(bindings.FPDFAnnot_GetNumberValue(handle, "CA", &mut opacity)) {
+            opacity = 1.0;
+        }
+
+        let mut ink_paths = vec![];
+        for path in 0..bindings.FPDFAnnot_GetInkListCount(handle) {
+            let len = bindings.FPDFAnnot_GetInkListPath(handle, path, std::ptr::null_mut(), 0);
+            let mut points = vec![unsafe { mem::zeroed() }; len as usize];
+            bindings.FPDFAnnot_GetInkListPath(handle, path, points.as_mut_ptr(), len);
+            ink_paths.push(points.iter().map(|point| [point.x, point.y]).collect());
+        }
+
+        let mut start = unsafe { mem::zeroed() };
+        let mut end = unsafe { mem::zeroed() };
+        let line = bindings
+            .is_true(bindings.FPDFAnnot_GetLine(handle, &mut start, &mut end))
+            .then_some([[start.x, start.y], [end.x, end.y]]);
+
+        let len = bindings.FPDFAnnot_GetVertices(handle, std::ptr::null_mut(), 0);
+        let mut vertices = vec![unsafe { mem::zeroed() }; len as usize];
+        bindings.FPDFAnnot_GetVertices(handle, vertices.as_mut_ptr(), len);
+        let vertices = vertices.iter().map(|point| [point.x, point.y]).collect();
+
+        let popup = bindings.FPDFAnnot_GetLinkedAnnot(handle, "Popup");
+        let popup_index = if popup.is_null() {
+            None
+        } else {
+            let popup_index = bindings.FPDFPage_GetAnnotIndex(page_handle, popup);
+            bindings.FPDFPage_CloseAnnot(popup);
+            Some(popup_index).filter(|index| *index >= 0)
+        };
+
+        let properties = RawProperties {
+            opacity,
+            flags: bindings.FPDFAnnot_GetFlags(handle),
+            ink_paths,
+            line,
+            vertices,
+            popup_index,
+        };
+        bindings.FPDFPage_CloseAnnot(handle);
+        Some(properties)
+    }
+}
+
+/// The page text under a markup annotation's quad points, one quad (usually one line)
+/// at a time. A character counts as covered when its center is inside a quad.
+fn marked_text(text: &PdfPageText, annotation: &PdfPageAnnotation) -> Option<String> {
+    let quads: Vec<PdfRect> = annotation
+        .attachment_points()
+        .iter()
+        .map(|quad| {
+            let xs = [quad.x1.value, quad.x2.value, quad.x3.value, quad.x4.value];
+            let ys = [quad.y1.value, quad.y2.value, quad.y3.value, quad.y4.value];
+            let min = |values: [f32; 4]| values.into_iter().fold(f32::MAX, f32::min);
+            let max = |values: [f32; 4]| values.into_iter().fold(f32::MIN, f32::max);
+            PdfRect::new_from_values(min(ys), min(xs), max(ys), max(xs))
+        })
+        .collect();
+    let mut runs: Vec<String> = vec![String::new(); quads.len()];
+    for c in text.chars().iter() {
+        let bounds = match c.loose_bounds() {
+            Ok(bounds) => bounds,
+            Err(_) => continue,
+        };
+        let x = bounds.left + bounds.width() / 2.0;
+        let y = bounds.bottom + bounds.height() / 2.0;
+        if let Some(i) = quads.iter().position(|quad| quad.contains(x, y)) {
+            if let Some(ch) = c.unicode_char() {
+                runs[i].push(ch);
+            }
+        }
+    }
+    let runs: Vec<&str> = runs
+        .iter()
+        .map(|run| run.trim())
+        .filter(|run| !run.is_empty())
+        .collect();
+    (!runs.is_empty()).then(|| runs.join(" "))
+}
+
+/// Converts a list of points into JSON, or NULL when there are none.
+fn points_json(points: &[[f32; 2]]) -> Option<Value> {
+    if points.is_empty() {
+        None
+    } else {
+        Some(json!(points))
+    }
+}
+
 #[repr(C)]
 pub struct PdfAnnotationsTable {
     /// must be first
@@ -107,7 +262,12 @@ pub struct PdfAnnotationsCursor<'vtab> {
     base: sqlite3_vtab_cursor,
     rowid: i64,
     document: Option<*const PdfDocument<'vtab>>,
+    page: Option<*mut PagePointer<'vtab>>,
     current: Option<PdfPageAnnotation<'vtab>>,
+    current_raw: Option<RawProperties>,
+    current_file: Option<EmbeddedFile>,
+    /// The page's text, loaded once in filter for the marked_text column
+    text: Option<PdfPageText<'vtab>>,
     iter: Option<PdfPageAnnotationsIterator<'vtab>>,
     phantom: PhantomData<&'vtab PdfAnnotationsTable>,
 }
@@ -118,7 +278,11 @@ impl PdfAnnotationsCursor<'_> {
             base,
             rowid: 0,
             document: None,
+            page: None,
             current: None,
+            current_raw: None,
+            current_file: None,
+            text: None,
             iter: None,
             phantom: PhantomData,
         }
@@ -132,10 +296,12 @@ impl VTabCursor for PdfAnnotationsCursor<'_> {
         _idx_str: Option<&str>,
         values: &[*mut sqlite3_value],
     ) -> Result<()> {
-        let page: *mut PagePointer = unsafe { api::value_pointer(&values[0], b"wut\0").unwrap() };
+        let page = value_page(&values[0])?;
         unsafe {
+            self.text = Some((*page).1.text().map_err(pdfium_error)?);
             self.iter = Some((*page).1.annotations().iter());
         }
+        self.page = Some(page);
 
         self.rowid = 0;
         self.next()?;
@@ -145,7 +311,25 @@ impl VTabCursor for PdfAnnotationsCursor<'_> {
 
     fn next(&mut self) -> Result<()> {
         self.rowid += 1;
-        self.current = self.iter.as_mut().unwrap().next();
+        self.current = self.iter.as_mut().and_then(|iter| iter.next());
+        self.current_raw = match (&self.current, self.page) {
+            (Some(_), Some(page)) => {
+                RawProperties::read(unsafe { &(*page).1 }, (self.rowid - 1) as usize)
+            }
+            _ => None,
+        };
+        self.current_file = None;
+        if let (Some(annotation), Some(page)) = (&self.current, self.page) {
+            if matches!(
+                annotation.annotation_type(),
+                PdfPageAnnotationType::FileAttachment
+            ) {
+                let raw = page_raw(page)?;
+                let page_index = unsafe { (*page).2 };
+                self.current_file =
+                    annotation_file(raw, page_index.into(), (self.rowid - 1) as usize);
+            }
+        }
 
         Ok(())
     }
@@ -155,8 +339,26 @@ impl VTabCursor for PdfAnnotationsCursor<'_> {
     }
 
     fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
-        let annotation = self.current.as_ref().unwrap();
-        //annotation.bounds()
+        let annotation = self.current.as_ref().ok_or_else(|| {
+            Error::new_message("pdf_annotations cursor has no current annotation")
+        })?;
+        let bounds = || {
+            annotation
+                .bounds()
+                .map_err(|_| Error::new_message("annotation is missing a bounds box"))
+        };
+        let raw = || {
+            self.current_raw
+                .as_ref()
+                .ok_or_else(|| Error::new_message("annotation could not be read from the page"))
+        };
+        let result_json = |value: Option<Value>| match value {
+            Some(value) => api::result_json(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
         match column(i) {
             Some(Columns::Type) => {
                 let typename = match annotation.annotation_type() {
@@ -192,18 +394,10 @@ impl VTabCursor for PdfAnnotationsCursor<'_> {
                 };
                 api::result_text(context, typename)?;
             }
-            Some(Columns::X) => {
-                api::result_double(context, annotation.bounds().unwrap().left.value.into())
-            }
-            Some(Columns::Y) => {
-                api::result_double(context, annotation.bounds().unwrap().top.value.into())
-            }
-            Some(Columns::Width) => {
-                api::result_double(context, annotation.bounds().unwrap().width().value.into())
-            }
-            Some(Columns::Height) => {
-                api::result_double(context, annotation.bounds().unwrap().height().value.into())
-            }
+            Some(Columns::X) => api::result_double(context, bounds()?.left.value.into()),
+            Some(Columns::Y) => api::result_double(context, bounds()?.top.value.into()),
+            Some(Columns::Width) => api::result_double(context, bounds()?.width().value.into()),
+            Some(Columns::Height) => api::result_double(context, bounds()?.height().value.into()),
             Some(Columns::Name) => match annotation.name() {
                 Some(name) => api::result_text(context, name)?,
                 None => api::result_null(context),
@@ -224,6 +418,89 @@ impl VTabCursor for PdfAnnotationsCursor<'_> {
                 Some(modification_date) => api::result_text(context, modification_date)?,
                 None => api::result_null(context),
             },
+            Some(Columns::FillColor) => match annotation.fill_color() {
+                Ok(color) => api::result_text(context, color_hex(&color))?,
+                Err(_) => api::result_null(context),
+            },
+            Some(Columns::StrokeColor) => match annotation.stroke_color() {
+                Ok(color) => api::result_text(context, color_hex(&color))?,
+                Err(_) => api::result_null(context),
+            },
+            Some(Columns::Opacity) => api::result_double(context, raw()?.opacity.into()),
+            Some(Columns::Flags) => api::result_int(context, raw()?.flags),
+            Some(Columns::Hidden) => api::result_bool(context, raw()?.flags & FLAG_HIDDEN != 0),
+            Some(Columns::Print) => api::result_bool(context, raw()?.flags & FLAG_PRINT != 0),
+            Some(Columns::Locked) => api::result_bool(context, raw()?.flags & FLAG_LOCKED != 0),
+            Some(Columns::QuadPoints) => {
+                let quads: Vec<Value> = if annotation.has_attachment_points() {
+                    annotation
+                        .attachment_points()
+                        .iter()
+                        .map(|quad| {
+                            json!([
+                                quad.x1.value,
+                                quad.y1.value,
+                                quad.x2.value,
+                                quad.y2.value,
+                                quad.x3.value,
+                                quad.y3.value,
+                                quad.x4.value,
+                                quad.y4.value,
+                            ])
+                        })
+                        .collect()
+                } else {
+                    vec![]
+                };
+                result_json((!quads.is_empty()).then_some(Value::Array(quads)))?;
+            }
+            Some(Columns::InkPaths) => {
+                let paths = &raw()?.ink_paths;
+                result_json((!paths.is_empty()).then_some(json!(paths)))?;
+            }
+            Some(Columns::Line) => result_json(raw()?.line.map(|line| json!(line)))?,
+            Some(Columns::Vertices) => result_json(points_json(&raw()?.vertices))?,
+            Some(Columns::PopupId) => match raw()?.popup_index {
+                // rowids are 1-based annotation indexes
+                Some(index) => api::result_int64(context, i64::from(index) + 1),
+                None => api::result_null(context),
+            },
+            Some(Columns::MarkedText) => {
+                let marked = match (annotation.annotation_type(), &self.text) {
+                    (
+                        PdfPageAnnotationType::Highlight
+                        | PdfPageAnnotationType::Underline
+                        | PdfPageAnnotationType::Strikeout
+                        | PdfPageAnnotationType::Squiggly,
+                        Some(text),
+                    ) => marked_text(text, annotation),
+                    _ => None,
+                };
+                match marked {
+                    Some(marked) => api::result_text(context, marked)?,
+                    None => api::result_null(context),
+                }
+            }
+            Some(Columns::AttachmentName) => {
+                match self
+                    .current_file
+                    .as_ref()
+                    .and_then(|file| file.name.as_ref())
+                {
+                    Some(name) => api::result_text(context, name)?,
+                    None => api::result_null(context),
+                }
+            }
+            Some(Columns::Attachment) => {
+                match self
+                    .current_file
+                    .as_ref()
+                    .and_then(|file| file.data.as_ref())
+                {
+                    Some(data) => api::result_blob(context, data),
+                    None => api::result_null(context),
+                }
+            }
             Some(Columns::Page) => {
                 api::result_null(context);
             }
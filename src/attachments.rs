@@ -0,0 +1,294 @@
+use pdfium_render::document::PdfDocument;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{
+    load_pdf,
+    metadata::pdf_date_iso8601,
+    pdfium, pdfium_error,
+    raw::{Object, RawPdf},
+    value_arg,
+};
+
+static CREATE_SQL: &str = "CREATE TABLE x(name text, size int, mime_type text, creation_date text, modification_date text, data blob, pdf hidden, password hidden)";
+enum Columns {
+    Name,
+    Size,
+    MimeType,
+    CreationDate,
+    ModificationDate,
+    Data,
+    Pdf,
+    Password,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Name),
+        1 => Some(Columns::Size),
+        2 => Some(Columns::MimeType),
+        3 => Some(Columns::CreationDate),
+        4 => Some(Columns::ModificationDate),
+        5 => Some(Columns::Data),
+        6 => Some(Columns::Pdf),
+        7 => Some(Columns::Password),
+        _ => None,
+    }
+}
+
+/// A file embedded through a file specification, read from the raw PDF bytes.
+pub(crate) struct EmbeddedFile {
+    pub(crate) name: Option<String>,
+    /// The stream's `/Subtype`, like `text/xml`
+    pub(crate) mime_type: Option<String>,
+    pub(crate) data: Option<Vec<u8>>,
+}
+
+/// Reads the file that a file specification points to.
+fn embedded_file(raw: &RawPdf, spec: &Object) -> Option<EmbeddedFile> {
+    let spec = raw.resolve(spec)?;
+    // a file specification can also be just a file name, pointing outside of the PDF
+    if let Object::String(_) = *spec {
+        return Some(EmbeddedFile {
+            name: spec.as_text(),
+            mime_type: None,
+            data: None,
+        });
+    }
+    let name = spec
+        .get(b"UF")
+        .or_else(|| spec.get(b"F"))
+        .and_then(Object::as_text);
+    let ef = spec.get(b"EF").and_then(|ef| raw.resolve(ef));
+    let stream = ef
+        .as_ref()
+        .and_then(|ef| ef.get(b"UF").or_else(|| ef.get(b"F")))
+        .and_then(|file| raw.object(file.as_reference()?));
+    let mime_type = stream
+        .as_ref()
+        .and_then(|stream| stream.value.get(b"Subtype")?.as_name())
+        .map(|name| String::from_utf8_lossy(name).into_owned());
+    Some(EmbeddedFile {
+        name,
+        mime_type,
+        data: stream.and_then(|stream| stream.decoded_stream()),
+    })
+}
+
+/// The file embedded in the file attachment annotation at `annotation_index` on the page
+/// at `page_index`, which pdfium doesn't expose. pdfium numbers a page's annotations in
+/// the order of its `/Annots` array.
+pub(crate) fn annotation_file(
+    raw: &RawPdf,
+    page_index: usize,
+    annotation_index: usize,
+) -> Option<EmbeddedFile> {
+    let page = raw.page_dictionary(page_index)?;
+    let annotations = raw.resolve(page.get(b"Annots")?)?;
+    let annotation = raw.resolve(annotations.as_array()?.get(annotation_index)?)?;
+    if annotation.get(b"Subtype").and_then(Object::as_name) != Some(b"FileAttachment") {
+        return None;
+    }
+    embedded_file(raw, annotation.get(b"FS")?)
+}
+
+/// The MIME types of the document's embedded files, by file name.
+fn mime_types(raw: &RawPdf) -> Vec<(String, String)> {
+    raw.objects_with(b"Type", b"Filespec")
+        .filter_map(|(_, spec)| {
+            let file = embedded_file(raw, &spec.value)?;
+            Some((file.name?, file.mime_type?))
+        })
+        .collect()
+}
+
+struct Attachment {
+    name: String,
+    data: Vec<u8>,
+    mime_type: Option<String>,
+    creation_date: Option<String>,
+    modification_date: Option<String>,
+}
+
+/// Reads the `/CreationDate` and `/ModDate` parameters of the attachment at `index`,
+/// which pdfium-render doesn't expose.
+fn attachment_dates(document: &PdfDocument, index: usize) -> (Option<String>, Option<String>) {
+    let bindings = document.bindings();
+    let handle =
+        bindings.FPDFDoc_GetAttachment(bindings.get_handle_from_document(document), index as c_int);
+    if handle.is_null() {
+        return (None, None);
+    }
+    let string_value = |key: &str| {
+        if !bindings.is_true(bindings.FPDFAttachment_HasKey(handle, key)) {
+            return None;
+        }
+        let len = bindings.FPDFAttachment_GetStringValue(handle, key, std::ptr::null_mut(), 0);
+        // the length is in bytes of UTF-16, including the terminating NUL
+        let mut buffer = vec![0u16; len as usize / 2];
+        bindings.FPDFAttachment_GetStringValue(handle, key, buffer.as_mut_ptr(), len);
+        let value = String::from_utf16_lossy(&buffer);
+        let value = value.trim_end_matches('\0');
+        // dates that don't follow the PDF date format are passed through as-is
+        (!value.is_empty()).then(|| pdf_date_iso8601(value).unwrap_or_else(|| value.to_owned()))
+    };
+    (string_value("CreationDate"), string_value("ModDate"))
+}
+
+#[repr(C)]
+pub struct PdfAttachmentsTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfAttachmentsTable {
+    type Aux = ();
+    type Cursor = PdfAttachmentsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfAttachmentsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfAttachmentsTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_pdf = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Pdf) => {
+                    has_pdf = true;
+                    1
+                }
+                Some(Columns::Password) => 2,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_pdf {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfAttachmentsCursor<'_>> {
+        Ok(PdfAttachmentsCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfAttachmentsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    attachments: Vec<Attachment>,
+    phantom: PhantomData<&'vtab PdfAttachmentsTable>,
+}
+impl PdfAttachmentsCursor<'_> {
+    fn new<'vtab>() -> PdfAttachmentsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfAttachmentsCursor {
+            base,
+            rowid: 0,
+            attachments: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfAttachmentsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let src = api::value_blob(&values[0]);
+        let password = match value_arg(values, 1) {
+            Some(value) => Some(api::value_text(value)?),
+            None => None,
+        };
+        let document = load_pdf(pdfium(), src, password, "pdf_attachments")?;
+        let mut attachments = vec![];
+        if !document.attachments().is_empty() {
+            // pdfium doesn't expose the embedded file streams' MIME types
+            let mime_types = mime_types(&RawPdf::from_document(&document)?);
+            for (index, attachment) in document.attachments().iter().enumerate() {
+                let name = attachment.name();
+                let (creation_date, modification_date) = attachment_dates(&document, index);
+                attachments.push(Attachment {
+                    data: attachment.save_to_bytes().map_err(pdfium_error)?,
+                    mime_type: mime_types
+                        .iter()
+                        .find(|(file, _)| *file == name)
+                        .map(|(_, mime_type)| mime_type.clone()),
+                    name,
+                    creation_date,
+                    modification_date,
+                });
+            }
+        }
+        self.attachments = attachments;
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.attachments.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let attachment = &self.attachments[self.rowid];
+        let result_text = |value: &Option<String>| match value {
+            Some(value) => api::result_text(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        match column(i) {
+            Some(Columns::Name) => api::result_text(context, &attachment.name)?,
+            Some(Columns::Size) => api::result_int64(context, attachment.data.len() as i64),
+            Some(Columns::MimeType) => result_text(&attachment.mime_type)?,
+            Some(Columns::CreationDate) => result_text(&attachment.creation_date)?,
+            Some(Columns::ModificationDate) => result_text(&attachment.modification_date)?,
+            Some(Columns::Data) => api::result_blob(context, &attachment.data),
+            Some(Columns::Pdf) | Some(Columns::Password) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
@@ -0,0 +1,236 @@
+use pdfium_render::bookmark::PdfBookmark;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Error, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{load_pdf, pdfium, value_arg};
+
+static CREATE_SQL: &str = "CREATE TABLE x(id int, title text, depth int, parent_id int, sibling_order int, page_index int, pdf hidden, password hidden)";
+enum Columns {
+    Id,
+    Title,
+    Depth,
+    ParentId,
+    SiblingOrder,
+    PageIndex,
+    Pdf,
+    Password,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Id),
+        1 => Some(Columns::Title),
+        2 => Some(Columns::Depth),
+        3 => Some(Columns::ParentId),
+        4 => Some(Columns::SiblingOrder),
+        5 => Some(Columns::PageIndex),
+        6 => Some(Columns::Pdf),
+        7 => Some(Columns::Password),
+        _ => None,
+    }
+}
+
+/// Malformed outlines can link back to themselves, so cap how far they're followed.
+const MAX_DEPTH: usize = 256;
+const MAX_BOOKMARKS: usize = 1 << 20;
+
+struct Bookmark {
+    title: Option<String>,
+    depth: usize,
+    parent_id: Option<usize>,
+    sibling_order: usize,
+    page_index: Option<u16>,
+}
+
+/// The index of the page a bookmark points to, either directly or through a GoTo action.
+fn bookmark_page_index(bookmark: &PdfBookmark) -> Option<u16> {
+    match bookmark.destination() {
+        Some(destination) => destination.page_index().ok(),
+        None => {
+            let action = bookmark.action()?;
+            let destination = action.as_local_destination_action()?.destination().ok()?;
+            destination.page_index().ok()
+        }
+    }
+}
+
+/// Walks the outline depth-first from `first` and its siblings, so every bookmark comes
+/// after its parent. Ids are 1-based positions in that order.
+fn collect_bookmarks(
+    first: Option<PdfBookmark>,
+    depth: usize,
+    parent_id: Option<usize>,
+    bookmarks: &mut Vec<Bookmark>,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(Error::new_message(
+            "PDF outline is nested too deeply, it may be malformed",
+        ));
+    }
+    let mut next = first;
+    let mut sibling_order = 0;
+    while let Some(bookmark) = next {
+        if bookmarks.len() >= MAX_BOOKMARKS {
+            return Err(Error::new_message(
+                "PDF outline has too many bookmarks, it may be malformed",
+            ));
+        }
+        bookmarks.push(Bookmark {
+            title: bookmark.title(),
+            depth,
+            parent_id,
+            sibling_order,
+            page_index: bookmark_page_index(&bookmark),
+        });
+        let id = bookmarks.len();
+        collect_bookmarks(bookmark.first_child(), depth + 1, Some(id), bookmarks)?;
+        next = bookmark.next_sibling();
+        sibling_order += 1;
+    }
+    Ok(())
+}
+
+#[repr(C)]
+pub struct PdfBookmarksTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfBookmarksTable {
+    type Aux = ();
+    type Cursor = PdfBookmarksCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfBookmarksTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfBookmarksTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_pdf = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Pdf) => {
+                    has_pdf = true;
+                    1
+                }
+                Some(Columns::Password) => 2,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_pdf {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfBookmarksCursor<'_>> {
+        Ok(PdfBookmarksCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfBookmarksCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    bookmarks: Vec<Bookmark>,
+    phantom: PhantomData<&'vtab PdfBookmarksTable>,
+}
+impl PdfBookmarksCursor<'_> {
+    fn new<'vtab>() -> PdfBookmarksCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfBookmarksCursor {
+            base,
+            rowid: 0,
+            bookmarks: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfBookmarksCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let src = api::value_blob(&values[0]);
+        let password = match value_arg(values, 1) {
+            Some(value) => Some(api::value_text(value)?),
+            None => None,
+        };
+        let document = load_pdf(pdfium(), src, password, "pdf_bookmarks")?;
+        let mut bookmarks = vec![];
+        collect_bookmarks(document.bookmarks().root(), 0, None, &mut bookmarks)?;
+        self.bookmarks = bookmarks;
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.bookmarks.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let bookmark = &self.bookmarks[self.rowid];
+        match column(i) {
+            Some(Columns::Id) => api::result_int64(context, self.rowid as i64 + 1),
+            Some(Columns::Title) => match &bookmark.title {
+                Some(title) => api::result_text(context, title)?,
+                None => api::result_null(context),
+            },
+            Some(Columns::Depth) => api::result_int64(context, bookmark.depth as i64),
+            Some(Columns::ParentId) => match bookmark.parent_id {
+                Some(parent_id) => api::result_int64(context, parent_id as i64),
+                None => api::result_null(context),
+            },
+            Some(Columns::SiblingOrder) => {
+                api::result_int64(context, bookmark.sibling_order as i64)
+            }
+            Some(Columns::PageIndex) => match bookmark.page_index {
+                Some(page_index) => api::result_int64(context, page_index.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Pdf) | Some(Columns::Password) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64 + 1)
+    }
+}
@@ -0,0 +1,303 @@
+use pdfium_render::{page::PdfPageRenderRotation, pages::PdfPageIndex};
+use serde_json::Value;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{api, Error, Result};
+
+use crate::{
+    load_pdf,
+    merge::parse_page_range,
+    pdfium, pdfium_error,
+    raw::{Object, RawPdf, MAX_DEPTH},
+    rotation_degrees, value_arg,
+};
+
+/// `pdf_rotate_pages(pdf, pages, degrees [, password])` turns the given pages clockwise by
+/// a multiple of 90 degrees, on top of any rotation they already have.
+pub fn pdf_rotate_pages(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    if values.len() != 3 && values.len() != 4 {
+        return Err(Error::new_message(
+            "pdf_rotate_pages() takes a PDF, a page range like '1-3,7,10-', degrees and an optional password",
+        ));
+    }
+    let degrees = api::value_int64(&values[2]);
+    if degrees % 90 != 0 {
+        return Err(Error::new_message(
+            "pdf_rotate_pages() degrees must be a multiple of 90",
+        ));
+    }
+    let password = match value_arg(values, 3) {
+        Some(value) => Some(api::value_text(value)?),
+        None => None,
+    };
+    let src = api::value_blob(&values[0]);
+    let document = load_pdf(pdfium(), src, password, "pdf_rotate_pages")?;
+    let mut pages = parse_page_range(api::value_text(&values[1])?, document.pages().len())?;
+    // a page listed twice, like in '1,1-3', is still only turned once
+    pages.sort_unstable();
+    pages.dedup();
+    for page_index in pages {
+        let mut page = document.pages().get(page_index).map_err(pdfium_error)?;
+        let current = page.rotation().map_err(pdfium_error)?;
+        page.set_rotation(
+            match (rotation_degrees(current) + degrees).rem_euclid(360) {
+                90 => PdfPageRenderRotation::Degrees90,
+                180 => PdfPageRenderRotation::Degrees180,
+                270 => PdfPageRenderRotation::Degrees270,
+                _ => PdfPageRenderRotation::None,
+            },
+        );
+    }
+    api::result_blob(context, &document.save_to_bytes().map_err(pdfium_error)?);
+    Ok(())
+}
+
+/// `pdf_delete_pages(pdf, pages [, password])` removes the given pages.
+pub fn pdf_delete_pages(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    if values.len() != 2 && values.len() != 3 {
+        return Err(Error::new_message(
+            "pdf_delete_pages() takes a PDF, a page range like '1-3,7,10-' and an optional password",
+        ));
+    }
+    let password = match value_arg(values, 2) {
+        Some(value) => Some(api::value_text(value)?),
+        None => None,
+    };
+    let src = api::value_blob(&values[0]);
+    let document = load_pdf(pdfium(), src, password, "pdf_delete_pages")?;
+    let mut pages = parse_page_range(api::value_text(&values[1])?, document.pages().len())?;
+    pages.sort_unstable();
+    pages.dedup();
+    if pages.len() == document.pages().len() as usize {
+        return Err(Error::new_message(
+            "pdf_delete_pages() can't delete every page of a PDF",
+        ));
+    }
+    // from the back, so the indexes of the pages still to delete don't shift
+    for page_index in pages.into_iter().rev() {
+        document
+            .pages()
+            .get(page_index)
+            .and_then(|page| page.delete())
+            .map_err(pdfium_error)?;
+    }
+    api::result_blob(context, &document.save_to_bytes().map_err(pdfium_error)?);
+    Ok(())
+}
+
+/// Page attributes that pages inherit from the page tree nodes above them.
+const INHERITABLE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// A page of the page tree, with the attributes it inherits from the nodes above it.
+struct PageNode {
+    id: (u32, u32),
+    dictionary: Object,
+    inherited: Vec<(Vec<u8>, Object)>,
+}
+
+/// Collects the pages under the page tree node `id`, in order.
+fn collect_pages(
+    raw: &RawPdf,
+    id: (u32, u32),
+    inherited: &[(Vec<u8>, Object)],
+    pages: &mut Vec<PageNode>,
+    depth: usize,
+) -> Option<()> {
+    // guard against page trees that loop back on themselves
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let node = raw.object(id)?.value;
+    let Some(kids) = node.get(b"Kids").and_then(Object::as_array) else {
+        pages.push(PageNode {
+            id,
+            dictionary: node,
+            inherited: inherited.to_vec(),
+        });
+        return Some(());
+    };
+    let mut inherited = inherited.to_vec();
+    for key in INHERITABLE_KEYS {
+        if let Some(value) = node.get(key) {
+            inherited.retain(|(k, _)| k != key);
+            inherited.push((key.to_vec(), value.clone()));
+        }
+    }
+    for kid in kids {
+        collect_pages(raw, kid.as_reference()?, &inherited, pages, depth + 1)?;
+    }
+    Some(())
+}
+
+/// The PDF with its pages in the given order, written as an incremental update that
+/// replaces the `/Kids` of the root of the page tree. Everything else in the document,
+/// like bookmarks, form fields, metadata and page labels, is left as it was.
+fn reordered_pdf(raw: &RawPdf, order: &[PdfPageIndex]) -> Option<Vec<u8>> {
+    let root_id = raw.catalog()?.get(b"Pages")?.as_reference()?;
+    let mut pages = vec![];
+    collect_pages(raw, root_id, &[], &mut pages, 0)?;
+    if pages.len() != order.len() {
+        return None;
+    }
+
+    let Object::Dictionary(mut root) = raw.object(root_id)?.value else {
+        return None;
+    };
+    root.retain(|(key, _)| key != b"Kids" && key != b"Count");
+    let kids = order
+        .iter()
+        .map(|index| {
+            let (number, generation) = pages[*index as usize].id;
+            Object::Reference(number, generation)
+        })
+        .collect();
+    root.push((b"Kids".to_vec(), Object::Array(kids)));
+    root.push((b"Count".to_vec(), Object::Number(order.len() as f64)));
+    let mut updated = vec![(root_id, Object::Dictionary(root))];
+    // pages from deeper in the tree move straight under the root, so they take along
+    // what they inherited from the nodes in between
+    for page in pages {
+        let parent = page
+            .dictionary
+            .get(b"Parent")
+            .and_then(Object::as_reference);
+        if parent == Some(root_id) {
+            continue;
+        }
+        let Object::Dictionary(mut entries) = page.dictionary else {
+            return None;
+        };
+        for (key, value) in page.inherited {
+            if !entries.iter().any(|(k, _)| *k == key) {
+                entries.push((key, value));
+            }
+        }
+        entries.retain(|(key, _)| key != b"Parent");
+        entries.push((b"Parent".to_vec(), Object::Reference(root_id.0, root_id.1)));
+        updated.push((page.id, Object::Dictionary(entries)));
+    }
+
+    raw.updated(&updated)
+}
+
+/// `pdf_reorder_pages(pdf, order [, password])` puts the pages in the order of a JSON
+/// array of page numbers, like `[3, 1, 2]`, which has to list every page exactly once.
+pub fn pdf_reorder_pages(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    if values.len() != 2 && values.len() != 3 {
+        return Err(Error::new_message(
+            "pdf_reorder_pages() takes a PDF, a JSON array of page numbers and an optional password",
+        ));
+    }
+    let password = match value_arg(values, 2) {
+        Some(value) => Some(api::value_text(value)?),
+        None => None,
+    };
+    let src = api::value_blob(&values[0]);
+    let document = load_pdf(pdfium(), src, password, "pdf_reorder_pages")?;
+    let page_count = document.pages().len();
+    let invalid = || {
+        Error::new_message(format!(
+            "pdf_reorder_pages() order must be a JSON array with each page number from 1 to {page_count} exactly once"
+        ))
+    };
+    let order: Vec<PdfPageIndex> = match serde_json::from_str(api::value_text(&values[1])?) {
+        Ok(Value::Array(numbers)) => numbers
+            .iter()
+            .map(|number| match number.as_u64() {
+                Some(number @ 1..) if number <= page_count as u64 => Ok(number as PdfPageIndex - 1),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<_>>()?,
+        _ => return Err(invalid()),
+    };
+    let mut sorted = order.clone();
+    sorted.sort_unstable();
+    if !sorted.iter().copied().eq(0..page_count) {
+        return Err(invalid());
+    }
+    let unreadable =
+        || Error::new_message("pdf_reorder_pages() couldn't rewrite the PDF's page tree");
+    let reordered =
+        reordered_pdf(&RawPdf::from_document(&document)?, &order).ok_or_else(unreadable)?;
+    // make sure pdfium reads the rewritten page tree back
+    let check = pdfium()
+        .load_pdf_from_byte_slice(&reordered, None)
+        .map_err(pdfium_error)?;
+    if check.pages().len() != page_count {
+        return Err(unreadable());
+    }
+    api::result_blob(context, &reordered);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reorders_nested_page_trees() {
+        let src = b"%PDF-1.7
+1 0 obj << /Type /Catalog /Pages 2 0 R /Outlines 9 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R 6 0 R] /Count 3 /MediaBox [0 0 612 792] >> endobj
+3 0 obj << /Type /Pages /Parent 2 0 R /Kids [4 0 R 5 0 R] /Count 2 /Rotate 90 >> endobj
+4 0 obj << /Type /Page /Parent 3 0 R /Name (first) >> endobj
+5 0 obj << /Type /Page /Parent 3 0 R /Name (second) /Rotate 0 >> endobj
+6 0 obj << /Type /Page /Parent 2 0 R /Name (third) >> endobj
+xref
+0 1
+0000000000 65535 f 
+trailer << /Size 10 /Root 1 0 R >>
+startxref
+400
+%%EOF
+";
+        let reordered = reordered_pdf(&RawPdf::new(&src[..]), &[2, 0, 1]).unwrap();
+        assert!(reordered.starts_with(src));
+        let raw = RawPdf::new(reordered);
+        let page = |index| raw.page_dictionary(index).unwrap();
+        let name = |page: &Object| page.get(b"Name").and_then(Object::as_text).unwrap();
+        assert_eq!(name(&page(0)), "third");
+        assert_eq!(name(&page(1)), "first");
+        assert_eq!(name(&page(2)), "second");
+        // pages that moved out of the nested node keep the rotation they inherited
+        let rotate = |page: &Object| page.get(b"Rotate").and_then(Object::as_number);
+        assert_eq!(rotate(&page(1)), Some(90.0));
+        assert_eq!(rotate(&page(2)), Some(0.0));
+        assert_eq!(
+            page(1).get(b"Parent").and_then(Object::as_reference),
+            Some((2, 0))
+        );
+        // the rest of the document stays as it was
+        let catalog = raw.catalog().unwrap();
+        assert_eq!(
+            catalog.get(b"Outlines").and_then(Object::as_reference),
+            Some((9, 0))
+        );
+        let trailer = raw.trailer().unwrap();
+        assert_eq!(
+            trailer.get(b"Prev").and_then(Object::as_number),
+            Some(400.0)
+        );
+    }
+
+    #[test]
+    fn rejects_orders_of_the_wrong_length() {
+        let src = b"1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj
+3 0 obj << /Type /Page /Parent 2 0 R >> endobj
+trailer << /Size 4 /Root 1 0 R >>
+startxref
+0
+%%EOF
+";
+        assert!(reordered_pdf(&RawPdf::new(&src[..]), &[0, 1]).is_none());
+    }
+}
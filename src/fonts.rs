@@ -0,0 +1,337 @@
+use pdfium_render::{
+    font::PdfFont, page_object::PdfPageObject, page_objects_common::PdfPageObjectsCommon,
+};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{collections::HashMap, marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{
+    font_weight_value, load_pdf, page_raw, pdfium,
+    raw::{Object, RawPdf},
+    value_arg, value_page,
+};
+
+static CREATE_SQL: &str = "CREATE TABLE x(name text, family text, weight int, italic int, embedded int, subset int, type text, text_objects int, source hidden, password hidden)";
+enum Columns {
+    Name,
+    Family,
+    Weight,
+    Italic,
+    Embedded,
+    Subset,
+    Type,
+    TextObjects,
+    Source,
+    Password,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Name),
+        1 => Some(Columns::Family),
+        2 => Some(Columns::Weight),
+        3 => Some(Columns::Italic),
+        4 => Some(Columns::Embedded),
+        5 => Some(Columns::Subset),
+        6 => Some(Columns::Type),
+        7 => Some(Columns::TextObjects),
+        8 => Some(Columns::Source),
+        9 => Some(Columns::Password),
+        _ => None,
+    }
+}
+
+struct Font {
+    name: Option<String>,
+    family: Option<String>,
+    weight: i64,
+    italic: bool,
+    embedded: bool,
+    subset: bool,
+    font_type: Option<String>,
+    text_objects: i64,
+}
+
+/// Whether a font name has the `ABCDEF+` tag of a font subset.
+fn is_subset_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() > 7 && bytes[6] == b'+' && bytes[..6].iter().all(u8::is_ascii_uppercase)
+}
+
+impl Font {
+    fn new(font: &PdfFont) -> Font {
+        let name = Some(font.name()).filter(|name| !name.is_empty());
+        let subset = name.as_deref().is_some_and(is_subset_name);
+        // pdfium has no weight or italic angle for fonts without a descriptor (like the
+        // standard 14 fonts), which have their style in the name instead
+        let style_name = name.as_deref().unwrap_or_default();
+        let weight = match font.weight() {
+            Ok(weight) => font_weight_value(weight).into(),
+            Err(_) if style_name.contains("Bold") => 700,
+            Err(_) => 400,
+        };
+        let italic = font.is_italic()
+            || font.italic_angle().is_ok_and(|angle| angle != 0)
+            || style_name.contains("Italic")
+            || style_name.contains("Oblique");
+        // like `ABCDEF+Arial-BoldMT` or `TimesNewRoman,Bold`
+        let family = name.as_deref().and_then(|name| {
+            let name = if subset { &name[7..] } else { name };
+            Some(name.split(['-', ',']).next()?.to_owned())
+        });
+        Font {
+            name,
+            family,
+            weight,
+            italic,
+            embedded: false,
+            subset,
+            font_type: None,
+            text_objects: 0,
+        }
+    }
+}
+
+/// Counts the text objects of each font in `objects`, and in the form XObjects among them.
+fn count_text_objects<'a>(
+    objects: impl Iterator<Item = PdfPageObject<'a>>,
+    fonts: &mut Vec<Font>,
+    depth: usize,
+) {
+    for object in objects {
+        if let Some(text) = object.as_text_object() {
+            let font = text.font();
+            let name = Some(font.name()).filter(|name| !name.is_empty());
+            let index = match fonts.iter().position(|f| f.name == name) {
+                Some(index) => index,
+                None => {
+                    fonts.push(Font::new(&font));
+                    fonts.len() - 1
+                }
+            };
+            fonts[index].text_objects += 1;
+        } else if let Some(form) = object.as_x_object_form_object() {
+            if depth < 16 {
+                count_text_objects(form.iter(), fonts, depth + 1);
+            }
+        }
+    }
+}
+
+/// The `/Subtype` of each font dictionary, and whether its font program is embedded, by
+/// the font's name. pdfium only exposes fonts by their names and metrics.
+fn font_dictionaries(raw: &RawPdf) -> HashMap<String, (String, bool)> {
+    let name_of = |value: Option<&Object>| {
+        value
+            .and_then(Object::as_name)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+    };
+    let mut dictionaries = HashMap::new();
+    for (_, object) in raw.objects_with(b"Type", b"Font") {
+        let font = &object.value;
+        let Some(name) = name_of(font.get(b"BaseFont")) else {
+            continue;
+        };
+        let mut font_type = name_of(font.get(b"Subtype")).unwrap_or_default();
+        // composite fonts describe their glyphs in a descendant CIDFont
+        let descendant = (font_type == "Type0")
+            .then(|| {
+                let descendants = raw.resolve(font.get(b"DescendantFonts")?)?;
+                Some(raw.resolve(descendants.as_array()?.first()?)?.into_owned())
+            })
+            .flatten();
+        if let Some(descendant) = &descendant {
+            font_type = name_of(descendant.get(b"Subtype")).unwrap_or(font_type);
+        }
+        let descriptor = descendant
+            .as_ref()
+            .unwrap_or(font)
+            .get(b"FontDescriptor")
+            .and_then(|descriptor| raw.resolve(descriptor));
+        // Type3 glyphs are drawn by content streams inside the PDF
+        let embedded = font_type == "Type3"
+            || descriptor.is_some_and(|descriptor| {
+                [&b"FontFile"[..], b"FontFile2", b"FontFile3"]
+                    .iter()
+                    .any(|key| descriptor.get(key).is_some())
+            });
+        dictionaries.entry(name).or_insert((font_type, embedded));
+    }
+    dictionaries
+}
+
+/// Fills in the type of each font, and whether it's embedded, from the font dictionaries
+/// of `raw`. Returns whether every font with a name was found.
+fn read_font_dictionaries(fonts: &mut [Font], raw: &RawPdf) -> bool {
+    let dictionaries = font_dictionaries(raw);
+    let mut found_all = true;
+    for font in fonts {
+        match font.name.as_ref().map(|name| dictionaries.get(name)) {
+            Some(Some((font_type, embedded))) => {
+                font.font_type = Some(font_type.clone());
+                font.embedded = *embedded;
+            }
+            Some(None) => found_all = false,
+            None => (),
+        }
+    }
+    found_all
+}
+
+#[repr(C)]
+pub struct PdfFontsTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfFontsTable {
+    type Aux = ();
+    type Cursor = PdfFontsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfFontsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfFontsTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_source = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Source) => {
+                    has_source = true;
+                    1
+                }
+                Some(Columns::Password) => 2,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_source {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfFontsCursor<'_>> {
+        Ok(PdfFontsCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfFontsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    fonts: Vec<Font>,
+    phantom: PhantomData<&'vtab PdfFontsTable>,
+}
+impl PdfFontsCursor<'_> {
+    fn new<'vtab>() -> PdfFontsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfFontsCursor {
+            base,
+            rowid: 0,
+            fonts: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfFontsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        // the source is either a page from pdf_pages(), or the blob of a whole PDF
+        let mut fonts = vec![];
+        match value_page(&values[0]) {
+            Ok(page) => {
+                count_text_objects(unsafe { (*page).1.objects().iter() }, &mut fonts, 0);
+                read_font_dictionaries(&mut fonts, page_raw(page)?);
+            }
+            Err(_) => {
+                let src = api::value_blob(&values[0]);
+                let password = match value_arg(values, 1) {
+                    Some(value) => Some(api::value_text(value)?),
+                    None => None,
+                };
+                let document = load_pdf(pdfium(), src, password, "pdf_fonts")?;
+                for page in document.pages().iter() {
+                    count_text_objects(page.objects().iter(), &mut fonts, 0);
+                }
+                // font dictionaries can usually be read straight from the file, and pdfium
+                // only has to save the document when they're compressed in object streams
+                if !read_font_dictionaries(&mut fonts, &RawPdf::new(src)) {
+                    read_font_dictionaries(&mut fonts, &RawPdf::from_document(&document)?);
+                }
+            }
+        }
+        self.fonts = fonts;
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.fonts.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let font = &self.fonts[self.rowid];
+        let result_text = |value: &Option<String>| match value {
+            Some(value) => api::result_text(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        match column(i) {
+            Some(Columns::Name) => result_text(&font.name)?,
+            Some(Columns::Family) => result_text(&font.family)?,
+            Some(Columns::Weight) => api::result_int64(context, font.weight),
+            Some(Columns::Italic) => api::result_bool(context, font.italic),
+            Some(Columns::Embedded) => api::result_bool(context, font.embedded),
+            Some(Columns::Subset) => api::result_bool(context, font.subset),
+            Some(Columns::Type) => result_text(&font.font_type)?,
+            Some(Columns::TextObjects) => api::result_int64(context, font.text_objects),
+            Some(Columns::Source) | Some(Columns::Password) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
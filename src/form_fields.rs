@@ -0,0 +1,847 @@
+use pdfium_render::{
+    document::PdfDocument,
+    fonts::PdfFontToken,
+    form_field::{PdfFormField, PdfFormFieldCommon},
+    page::PdfPage,
+    page_annotation::PdfPageAnnotationCommon,
+    page_object::PdfPageObjectCommon,
+    page_object_text::PdfPageTextObject,
+    page_objects_common::PdfPageObjectsCommon,
+    pages::PdfPageIndex,
+    points::PdfPoints,
+    rect::PdfRect,
+};
+use serde_json::{Map, Value};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Error, Result,
+};
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    marker::PhantomData,
+    mem,
+    os::raw::c_int,
+};
+
+use crate::{
+    load_pdf, pdfium, pdfium_error,
+    raw::{Object, RawPdf},
+    value_arg, value_page,
+};
+
+static CREATE_SQL: &str = "CREATE TABLE x(name text, full_name text, type text, value text, default_value text, options, checked int, read_only int, required int, x, y, width, height, page_index int, source hidden, password hidden)";
+enum Columns {
+    Name,
+    FullName,
+    Type,
+    Value,
+    DefaultValue,
+    Options,
+    Checked,
+    ReadOnly,
+    Required,
+    X,
+    Y,
+    Width,
+    Height,
+    PageIndex,
+    Source,
+    Password,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Name),
+        1 => Some(Columns::FullName),
+        2 => Some(Columns::Type),
+        3 => Some(Columns::Value),
+        4 => Some(Columns::DefaultValue),
+        5 => Some(Columns::Options),
+        6 => Some(Columns::Checked),
+        7 => Some(Columns::ReadOnly),
+        8 => Some(Columns::Required),
+        9 => Some(Columns::X),
+        10 => Some(Columns::Y),
+        11 => Some(Columns::Width),
+        12 => Some(Columns::Height),
+        13 => Some(Columns::PageIndex),
+        14 => Some(Columns::Source),
+        15 => Some(Columns::Password),
+        _ => None,
+    }
+}
+
+// field flags, from section 12.7.3.1 of the PDF 1.7 spec
+const FIELD_READ_ONLY: i64 = 1;
+const FIELD_REQUIRED: i64 = 1 << 1;
+const FIELD_MULTILINE: i64 = 1 << 12;
+
+// pdfium's object types, as returned by FPDFAnnot_GetValueType()
+const FPDF_OBJECT_STRING: c_int = 3;
+const FPDF_OBJECT_NAME: c_int = 4;
+const FPDF_OBJECT_NUMBER: c_int = 2;
+
+/// Form fields are trees, so cap how far up the `/Parent` chain inherited keys are looked for.
+const MAX_FIELD_DEPTH: usize = 32;
+
+/// A single widget of a form field. Fields with several widgets (like radio button
+/// groups) have one row per widget.
+struct FormField {
+    full_name: Option<String>,
+    field_type: &'static str,
+    value: Option<String>,
+    default_value: Option<String>,
+    options: Option<Vec<String>>,
+    checked: Option<bool>,
+    flags: i64,
+    bounds: Option<PdfRect>,
+    page_index: Option<u16>,
+}
+
+/// Field keys that can be inherited from a widget's parent fields, and that pdfium-render
+/// doesn't expose: the field flags, the default value, the default appearance and the
+/// alignment of text.
+struct InheritedKeys {
+    flags: i64,
+    default_value: Option<String>,
+    default_appearance: Option<String>,
+    /// The `/Q` quadding of text fields: 0 for left-aligned, 1 for centered, 2 for right-aligned
+    alignment: i64,
+}
+
+impl InheritedKeys {
+    /// Reads the inherited keys of the widget annotation at `index` on the page. Keys the
+    /// widget doesn't have itself are looked for up the chain of fields from `parents`:
+    /// pdfium only links to other annotations, and parent fields aren't annotations.
+    fn read(
+        page: &PdfPage,
+        index: usize,
+        parents: impl FnOnce() -> Result<Vec<Object>>,
+    ) -> Result<InheritedKeys> {
+        let bindings = page.bindings();
+        let handle =
+            bindings.FPDFPage_GetAnnot(bindings.get_handle_from_page(page), index as c_int);
+        let mut keys = InheritedKeys {
+            flags: 0,
+            default_value: None,
+            default_appearance: None,
+            alignment: 0,
+        };
+        let (mut flags, mut alignment) = (None, None);
+        if !handle.is_null() {
+            let number_value = |key: &str| {
+                if bindings.FPDFAnnot_GetValueType(handle, key) != FPDF_OBJECT_NUMBER {
+                    return None;
+                }
+                let mut value = 0.0;
+                // pdfium reads numbers as floats, which only hold flags below 2^24 exactly,
+                // so larger ones are read from the raw dictionary instead
+                (bindings.is_true(bindings.FPDFAnnot_GetNumberValue(handle, key, &mut value))
+                    && value < (1 << f32::MANTISSA_DIGITS) as f32)
+                    .then_some(value as i64)
+            };
+            flags = number_value("Ff");
+            alignment = number_value("Q");
+            let string_value = |key: &str| {
+                let value_type = bindings.FPDFAnnot_GetValueType(handle, key);
+                if value_type != FPDF_OBJECT_STRING && value_type != FPDF_OBJECT_NAME {
+                    return None;
+                }
+                let len = bindings.FPDFAnnot_GetStringValue(handle, key, std::ptr::null_mut(), 0);
+                // the length is in bytes of UTF-16, including the terminating NUL
+                let mut buffer = vec![0u16; len as usize / 2];
+                bindings.FPDFAnnot_GetStringValue(handle, key, buffer.as_mut_ptr(), len);
+                let value = String::from_utf16_lossy(&buffer);
+                Some(value.trim_end_matches('\0').to_owned())
+            };
+            keys.default_value = string_value("DV");
+            keys.default_appearance = string_value("DA");
+            bindings.FPDFPage_CloseAnnot(handle);
+        }
+        if flags.is_none()
+            || alignment.is_none()
+            || keys.default_value.is_none()
+            || keys.default_appearance.is_none()
+        {
+            let chain = parents()?;
+            let inherited = |key: &[u8]| chain.iter().find_map(|field| field.get(key));
+            flags = flags.or_else(|| Some(inherited(b"Ff")?.as_number()? as i64));
+            alignment = alignment.or_else(|| Some(inherited(b"Q")?.as_number()? as i64));
+            keys.default_value = keys.default_value.or_else(|| match inherited(b"DV")? {
+                Object::Name(name) => Some(String::from_utf8_lossy(name).into_owned()),
+                value => value.as_text(),
+            });
+            keys.default_appearance = keys
+                .default_appearance
+                .or_else(|| inherited(b"DA")?.as_text());
+        }
+        keys.alignment = alignment.unwrap_or(0);
+        keys.flags = flags.unwrap_or(0);
+        keys.default_value = keys.default_value.filter(|value| !value.is_empty());
+        Ok(keys)
+    }
+
+    /// The font size from the default appearance, like the 12 in `/Helv 12 Tf 0 g`. A size
+    /// of 0 means the text is auto-sized to fit the widget.
+    fn font_size(&self) -> Option<f32> {
+        let appearance = self.default_appearance.as_deref()?;
+        let tokens: Vec<&str> = appearance.split_whitespace().collect();
+        let tf = tokens.iter().position(|token| *token == "Tf")?;
+        let size: f32 = tokens.get(tf.checked_sub(1)?)?.parse().ok()?;
+        (size > 0.0).then_some(size)
+    }
+}
+
+/// The widget annotation at `index` on a page, followed by its parent fields. The raw
+/// reader is only created the first time a widget needs it.
+fn field_chain(
+    document: &PdfDocument,
+    raw: &mut Option<RawPdf<'static>>,
+    page_index: usize,
+    index: usize,
+) -> Result<Vec<Object>> {
+    let raw = match raw {
+        Some(raw) => raw,
+        None => raw.insert(RawPdf::from_document(document)?),
+    };
+    let widget = raw.page_dictionary(page_index).and_then(|page| {
+        let annotations = raw.resolve(page.get(b"Annots")?)?;
+        Some(
+            raw.resolve(annotations.as_array()?.get(index)?)?
+                .into_owned(),
+        )
+    });
+    let mut chain = vec![];
+    let mut field = widget;
+    while let Some(dictionary) = field {
+        if chain.len() >= MAX_FIELD_DEPTH {
+            break;
+        }
+        field = dictionary
+            .get(b"Parent")
+            .and_then(|parent| Some(raw.resolve(parent)?.into_owned()));
+        chain.push(dictionary);
+    }
+    Ok(chain)
+}
+
+/// Lists the form field widgets on the page at `page_index` of the document.
+fn page_fields(
+    document: &PdfDocument,
+    page: &PdfPage,
+    page_index: PdfPageIndex,
+    raw: &mut Option<RawPdf<'static>>,
+    fields: &mut Vec<FormField>,
+) -> Result<()> {
+    for (index, annotation) in page.annotations().iter().enumerate() {
+        let field = match annotation
+            .as_widget_annotation()
+            .and_then(|widget| widget.form_field())
+        {
+            Some(field) => field,
+            None => continue,
+        };
+        let (field_type, value, options, checked) = match field {
+            PdfFormField::Text(field) => ("text", field.value(), None, None),
+            PdfFormField::Checkbox(field) => (
+                "checkbox",
+                field.group_value(),
+                None,
+                field.is_checked().ok(),
+            ),
+            PdfFormField::RadioButton(field) => {
+                ("radio", field.group_value(), None, field.is_checked().ok())
+            }
+            PdfFormField::ComboBox(field) => {
+                let options = field.options().iter().filter_map(|o| o.label().cloned());
+                ("combo", field.value(), Some(options.collect()), None)
+            }
+            PdfFormField::ListBox(field) => {
+                let options = field.options().iter().filter_map(|o| o.label().cloned());
+                ("list", field.value(), Some(options.collect()), None)
+            }
+            PdfFormField::Signature(_) => ("signature", None, None, None),
+            PdfFormField::PushButton(_) => ("push_button", None, None, None),
+            PdfFormField::Unknown(_) => ("unknown", None, None, None),
+        };
+        let keys = InheritedKeys::read(page, index, || {
+            field_chain(document, raw, page_index.into(), index)
+        })?;
+        fields.push(FormField {
+            full_name: field.name(),
+            field_type,
+            value,
+            default_value: keys.default_value,
+            options,
+            checked,
+            flags: keys.flags,
+            bounds: annotation.bounds().ok(),
+            page_index: Some(page_index),
+        });
+    }
+    Ok(())
+}
+
+#[repr(C)]
+pub struct PdfFormFieldsTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfFormFieldsTable {
+    type Aux = ();
+    type Cursor = PdfFormFieldsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfFormFieldsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfFormFieldsTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_source = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Source) => {
+                    has_source = true;
+                    1
+                }
+                Some(Columns::Password) => 2,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_source {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfFormFieldsCursor<'_>> {
+        Ok(PdfFormFieldsCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfFormFieldsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    fields: Vec<FormField>,
+    phantom: PhantomData<&'vtab PdfFormFieldsTable>,
+}
+impl PdfFormFieldsCursor<'_> {
+    fn new<'vtab>() -> PdfFormFieldsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfFormFieldsCursor {
+            base,
+            rowid: 0,
+            fields: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfFormFieldsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let mut fields = vec![];
+        let mut raw = None;
+        // the source is either a page from pdf_pages(), or the blob of a whole PDF
+        match value_page(&values[0]) {
+            Ok(page) => {
+                let (document, page, page_index, _) = unsafe { &*page };
+                page_fields(
+                    unsafe { &**document },
+                    page,
+                    *page_index,
+                    &mut raw,
+                    &mut fields,
+                )?;
+                // the page's own index is already known to the query
+                for field in &mut fields {
+                    field.page_index = None;
+                }
+            }
+            Err(_) => {
+                let src = api::value_blob(&values[0]);
+                let password = match value_arg(values, 1) {
+                    Some(value) => Some(api::value_text(value)?),
+                    None => None,
+                };
+                let document = load_pdf(pdfium(), src, password, "pdf_form_fields")?;
+                for (page_index, page) in document.pages().iter().enumerate() {
+                    page_fields(&document, &page, page_index as u16, &mut raw, &mut fields)?;
+                }
+            }
+        }
+        self.fields = fields;
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.fields.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let field = &self.fields[self.rowid];
+        let result_text = |value: Option<&str>| match value {
+            Some(value) => api::result_text(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        let result_bound = |value: Option<f32>| match value {
+            Some(value) => api::result_double(context, value.into()),
+            None => api::result_null(context),
+        };
+        match column(i) {
+            Some(Columns::Name) => {
+                // the partial name is the last part of the fully-qualified `parent.child` name
+                let name = field
+                    .full_name
+                    .as_deref()
+                    .and_then(|name| name.rsplit('.').next());
+                result_text(name)?
+            }
+            Some(Columns::FullName) => result_text(field.full_name.as_deref())?,
+            Some(Columns::Type) => api::result_text(context, field.field_type)?,
+            Some(Columns::Value) => result_text(field.value.as_deref())?,
+            Some(Columns::DefaultValue) => result_text(field.default_value.as_deref())?,
+            Some(Columns::Options) => match &field.options {
+                Some(options) => api::result_json(context, serde_json::json!(options))?,
+                None => api::result_null(context),
+            },
+            Some(Columns::Checked) => match field.checked {
+                Some(checked) => api::result_bool(context, checked),
+                None => api::result_null(context),
+            },
+            Some(Columns::ReadOnly) => {
+                api::result_bool(context, field.flags & FIELD_READ_ONLY != 0)
+            }
+            Some(Columns::Required) => api::result_bool(context, field.flags & FIELD_REQUIRED != 0),
+            Some(Columns::X) => result_bound(field.bounds.map(|b| b.left.value)),
+            Some(Columns::Y) => result_bound(field.bounds.map(|b| b.top.value)),
+            Some(Columns::Width) => result_bound(field.bounds.map(|b| b.width().value)),
+            Some(Columns::Height) => result_bound(field.bounds.map(|b| b.height().value)),
+            Some(Columns::PageIndex) => match field.page_index {
+                Some(page_index) => api::result_int64(context, page_index.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Source) | Some(Columns::Password) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
+
+/// A value to write into a form field.
+enum FillValue {
+    Text(String),
+    /// Checks a checkbox, whatever its checked state is named
+    On,
+    /// Selects the checkbox or radio button state with this name
+    State(String),
+    Off,
+}
+
+impl FillValue {
+    fn from_json(name: &str, field: &PdfFormField, value: &Value) -> Result<FillValue> {
+        let invalid = |expected: &str| {
+            Err(Error::new_message(format!(
+                "pdf_fill_form() value for form field '{name}' must be {expected}"
+            )))
+        };
+        match field {
+            PdfFormField::Text(_) | PdfFormField::ComboBox(_) | PdfFormField::ListBox(_) => {
+                match value {
+                    Value::String(value) => Ok(FillValue::Text(value.clone())),
+                    Value::Number(_) | Value::Bool(_) => Ok(FillValue::Text(value.to_string())),
+                    Value::Null => Ok(FillValue::Text(String::new())),
+                    _ => invalid("a string"),
+                }
+            }
+            PdfFormField::Checkbox(_) => match value {
+                Value::Bool(true) => Ok(FillValue::On),
+                Value::Bool(false) | Value::Null => Ok(FillValue::Off),
+                Value::String(state) => Ok(FillValue::State(state.clone())),
+                _ => invalid("true, false or the name of its checked state"),
+            },
+            PdfFormField::RadioButton(_) => match value {
+                Value::String(state) => Ok(FillValue::State(state.clone())),
+                Value::Null => Ok(FillValue::Off),
+                _ => invalid("the name of the option to select"),
+            },
+            _ => Err(Error::new_message(format!(
+                "pdf_fill_form() can't fill form field '{name}', only text, choice, checkbox and radio button fields can be filled"
+            ))),
+        }
+    }
+}
+
+/// The objects changed by filling in the form, by id. Each is read from the raw PDF the
+/// first time it's changed.
+type Edits = BTreeMap<(u32, u32), Object>;
+
+fn edit<'e>(raw: &RawPdf, edits: &'e mut Edits, id: (u32, u32)) -> Option<&'e mut Object> {
+    match edits.entry(id) {
+        Entry::Occupied(entry) => Some(entry.into_mut()),
+        Entry::Vacant(entry) => Some(entry.insert(raw.object(id)?.value)),
+    }
+}
+
+/// A PDF text string: PDFDocEncoding agrees with ASCII, anything else is written as UTF-16BE.
+fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::String(text.as_bytes().to_vec());
+    }
+    let mut bytes = vec![0xFE, 0xFF];
+    for unit in text.encode_utf16() {
+        bytes.extend(unit.to_be_bytes());
+    }
+    Object::String(bytes)
+}
+
+/// The states a checkbox or radio button widget has a normal appearance for, besides `Off`.
+fn on_states(raw: &RawPdf, widget: &Object) -> Vec<Vec<u8>> {
+    let states = || {
+        let appearances = raw.resolve(widget.get(b"AP")?)?;
+        let normal = raw.resolve(appearances.get(b"N")?)?;
+        match normal.into_owned() {
+            Object::Dictionary(states) => Some(states),
+            _ => None,
+        }
+    };
+    let states = states().unwrap_or_default();
+    states
+        .into_iter()
+        .map(|(state, _)| state)
+        .filter(|state| state != b"Off")
+        .collect()
+}
+
+/// Writes a value into the field of the widget annotation with the given id. Returns
+/// `Some(false)` for checkboxes and radio buttons that don't have the given state.
+fn write_field(
+    raw: &RawPdf,
+    edits: &mut Edits,
+    widget_id: (u32, u32),
+    value: &FillValue,
+) -> Option<bool> {
+    let widget = edit(raw, edits, widget_id)?.clone();
+    // the value belongs to the terminal field, which is the widget itself when the two are
+    // merged into one dictionary
+    let field_id = match widget.get(b"T") {
+        Some(_) => widget_id,
+        None => widget.get(b"Parent")?.as_reference()?,
+    };
+    let state = match value {
+        FillValue::Text(text) => {
+            edit(raw, edits, field_id)?.set(b"V", text_string(text));
+            // the appearance stream still shows the old value, so remove it to have viewers
+            // regenerate it from the new one
+            edit(raw, edits, widget_id)?.remove(b"AP");
+            return Some(true);
+        }
+        FillValue::On => on_states(raw, &widget).into_iter().next(),
+        FillValue::State(name) => on_states(raw, &widget)
+            .into_iter()
+            .find(|state| state == name.as_bytes()),
+        FillValue::Off => None,
+    };
+    let off = matches!(value, FillValue::Off);
+    let appearance = Object::Name(state.clone().unwrap_or_else(|| b"Off".to_vec()));
+    edit(raw, edits, widget_id)?.set(b"AS", appearance.clone());
+    // only one radio button of a group has the selected state, the others leave the value be
+    if state.is_some() || off {
+        edit(raw, edits, field_id)?.set(b"V", appearance);
+    }
+    Some(state.is_some() || off)
+}
+
+/// Sets `/NeedAppearances` in the document's form, so that viewers draw the fields whose
+/// appearance streams were removed.
+fn need_appearances(raw: &RawPdf, edits: &mut Edits) -> Option<()> {
+    let root = raw.trailer()?.get(b"Root")?.as_reference()?;
+    let form = raw.object(root)?.value.get(b"AcroForm")?.clone();
+    match form {
+        Object::Reference(number, generation) => {
+            edit(raw, edits, (number, generation))?.set(b"NeedAppearances", Object::Boolean(true))
+        }
+        mut form => {
+            form.set(b"NeedAppearances", Object::Boolean(true));
+            edit(raw, edits, root)?.set(b"AcroForm", form);
+        }
+    }
+    Some(())
+}
+
+/// Splits text into the lines of a multiline field, wrapping at spaces so that each line
+/// is at most `width` wide, unless it's a single word.
+fn wrap_lines(text: &str, width: f32, measure: impl Fn(&str) -> f32) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let candidate = match line.is_empty() {
+                true => word.to_owned(),
+                false => format!("{line} {word}"),
+            };
+            if !line.is_empty() && measure(&candidate) > width {
+                lines.push(mem::replace(&mut line, word.to_owned()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Draws the value of a text field into its widget's rectangle on the page, the way a
+/// viewer would show it: aligned by its `/Q`, and wrapped onto several lines when the
+/// field is multiline.
+fn draw_text(
+    document: &PdfDocument,
+    page: &mut PdfPage,
+    bounds: PdfRect,
+    text: &str,
+    keys: &InheritedKeys,
+    font: PdfFontToken,
+) -> Result<()> {
+    let font_size = keys
+        .font_size()
+        .unwrap_or_else(|| (bounds.height().value * 0.6).min(12.0));
+    let padding = 2.0;
+    let width = bounds.width().value - 2.0 * padding;
+    let multiline = keys.flags & FIELD_MULTILINE != 0;
+    let lines = match multiline {
+        true => wrap_lines(text, width, |line| {
+            PdfPageTextObject::new(document, line, font, PdfPoints::new(font_size))
+                .and_then(|object| object.width())
+                .map_or(0.0, |width| width.value)
+        }),
+        false => vec![text.replace(['\r', '\n'], " ")],
+    };
+    // multiline text starts at the top, single lines are vertically centered, assuming
+    // capitals are about 0.7em tall
+    let first_y = match multiline {
+        true => bounds.top.value - padding - font_size,
+        false => bounds.bottom.value + (bounds.height().value - font_size * 0.7) / 2.0,
+    };
+    for (i, line) in lines.iter().enumerate() {
+        let y = first_y - i as f32 * font_size * 1.15;
+        // like in a viewer, lines that don't fit into the widget are cut off
+        if multiline && y < bounds.bottom.value {
+            break;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let mut object = page
+            .objects_mut()
+            .create_text_object(
+                PdfPoints::new(bounds.left.value + padding),
+                PdfPoints::new(y),
+                line,
+                font,
+                PdfPoints::new(font_size),
+            )
+            .map_err(pdfium_error)?;
+        let line_width = object.width().map_err(pdfium_error)?.value;
+        let offset = match keys.alignment {
+            1 => (width - line_width) / 2.0,
+            2 => width - line_width,
+            _ => 0.0,
+        };
+        if offset > 0.0 {
+            object
+                .translate(PdfPoints::new(offset), PdfPoints::ZERO)
+                .map_err(pdfium_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// pdf_fill_form(pdf, values [, flatten [, password]])
+pub fn pdf_fill_form(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    if values.len() < 2 || values.len() > 4 {
+        return Err(Error::new_message(
+            "pdf_fill_form() takes between 2 and 4 arguments",
+        ));
+    }
+    let src = api::value_blob(&values[0]);
+    let fill: Map<String, Value> = match serde_json::from_str(api::value_text(&values[1])?) {
+        Ok(Value::Object(fill)) => fill,
+        _ => {
+            return Err(Error::new_message(
+                "pdf_fill_form() values must be a JSON object of field names to values",
+            ))
+        }
+    };
+    let flatten = value_arg(values, 2).is_some_and(|value| api::value_int(value) != 0);
+    let password = match value_arg(values, 3) {
+        Some(value) => Some(api::value_text(value)?),
+        None => None,
+    };
+
+    // pdfium finds the fields, and the values are written straight into the PDF's objects,
+    // since pdfium can only write strings into annotations and can't reach parent fields
+    let document = load_pdf(pdfium(), src, password, "pdf_fill_form")?;
+    let raw = RawPdf::from_document(&document)?;
+    let mut edits = Edits::new();
+    // for each field that was filled, whether all of its values were found
+    let mut filled: HashMap<&str, bool> = HashMap::new();
+    // the page and annotation indexes of the text widgets to draw when flattening
+    let mut texts = vec![];
+    for (page_index, page) in document.pages().iter().enumerate() {
+        let widget_ids = raw.page_dictionary(page_index).and_then(|page| {
+            let annotations = raw.resolve(page.get(b"Annots")?)?.into_owned();
+            let ids = annotations.as_array()?.iter().map(Object::as_reference);
+            Some(ids.collect::<Vec<_>>())
+        });
+        for (index, annotation) in page.annotations().iter().enumerate() {
+            let field = match annotation
+                .as_widget_annotation()
+                .and_then(|widget| widget.form_field())
+            {
+                Some(field) => field,
+                None => continue,
+            };
+            let (name, value) = match field.name().and_then(|name| fill.get_key_value(&name)) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let value = FillValue::from_json(name, field, value)?;
+            let found = widget_ids
+                .as_ref()
+                .and_then(|ids| *ids.get(index)?)
+                .and_then(|id| write_field(&raw, &mut edits, id, &value))
+                .ok_or_else(|| {
+                    Error::new_message(format!(
+                        "pdf_fill_form() could not write form field '{name}'"
+                    ))
+                })?;
+            *filled.entry(name).or_default() |= found;
+            if let FillValue::Text(text) = value {
+                texts.push((page_index, index, text));
+            }
+        }
+    }
+    if let Some(name) = fill.keys().find(|name| !filled.contains_key(name.as_str())) {
+        return Err(Error::new_message(format!(
+            "pdf_fill_form() found no form field named '{name}'"
+        )));
+    }
+    if let Some((name, _)) = filled.iter().find(|(_, found)| !**found) {
+        return Err(Error::new_message(format!(
+            "pdf_fill_form() value {} is not a state of form field '{name}'",
+            fill[*name]
+        )));
+    }
+    if !texts.is_empty() {
+        need_appearances(&raw, &mut edits);
+    }
+    let edits: Vec<_> = edits.into_iter().collect();
+    let bytes = raw
+        .updated(&edits)
+        .ok_or_else(|| Error::new_message("pdf_fill_form() could not write the filled PDF"))?;
+    if !flatten {
+        api::result_blob(context, &bytes);
+        return Ok(());
+    }
+
+    let mut document = pdfium()
+        .load_pdf_from_byte_slice(&bytes, None)
+        .map_err(pdfium_error)?;
+    let font = document.fonts_mut().helvetica();
+    // the edits only changed values, so the raw PDF still has the same pages and fields
+    let mut raw = Some(raw);
+    for (page_index, mut page) in document.pages().iter().enumerate() {
+        // text fields no longer have an appearance that pdfium could flatten, so draw their
+        // values on the page instead. Removing widgets shifts the indexes after them.
+        for (_, index, text) in texts.iter().rev().filter(|text| text.0 == page_index) {
+            let keys = InheritedKeys::read(&page, *index, || {
+                field_chain(&document, &mut raw, page_index, *index)
+            })?;
+            let annotation = page.annotations().get(*index).map_err(pdfium_error)?;
+            let bounds = annotation.bounds().ok();
+            page.annotations_mut()
+                .delete_annotation(annotation)
+                .map_err(pdfium_error)?;
+            if let Some(bounds) = bounds.filter(|_| !text.is_empty()) {
+                draw_text(&document, &mut page, bounds, text, &keys, font)?;
+            }
+        }
+        page.flatten().map_err(pdfium_error)?;
+    }
+    let bytes = document.save_to_bytes().map_err(pdfium_error)?;
+    api::result_blob(context, &bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_lines_at_spaces() {
+        let measure = |line: &str| line.len() as f32;
+        assert_eq!(
+            wrap_lines("the quick brown fox\njumps", 10.0, measure),
+            ["the quick", "brown fox", "jumps"]
+        );
+        assert_eq!(
+            wrap_lines("incomprehensibilities", 10.0, measure),
+            ["incomprehensibilities"]
+        );
+        assert_eq!(wrap_lines("a\n\nb", 10.0, measure), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn encodes_text_strings() {
+        let mut written = vec![];
+        text_string("Zoë").write(&mut written);
+        assert_eq!(written, b"<FEFF005A006F00EB>");
+        let mut written = vec![];
+        text_string("Zoe").write(&mut written);
+        assert_eq!(written, b"<5A6F65>");
+    }
+}
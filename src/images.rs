@@ -1,4 +1,3 @@
-use image::ImageOutputFormat;
 use pdfium_render::{
     document::PdfDocument,
     page_object::{PdfPageObject, PdfPageObjectCommon},
@@ -9,17 +8,16 @@ use sqlite_loadable::prelude::*;
 use sqlite_loadable::{
     api,
     table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
-    BestIndexError, Result,
+    BestIndexError, Error, Result,
 };
 
-use std::{
-    io::{Read, Seek},
-    marker::PhantomData,
-    mem,
-    os::raw::c_int,
-};
+use std::{marker::PhantomData, mem, os::raw::c_int};
 
-use crate::PagePointer;
+use crate::{
+    pdfium_error,
+    render::{encode_image, OutputFormat},
+    value_page,
+};
 
 static CREATE_SQL: &str = "CREATE TABLE x(x, y, width, height, image, page hidden)";
 enum Columns {
@@ -128,7 +126,7 @@ impl VTabCursor for PdfImagesCursor<'_> {
         _idx_str: Option<&str>,
         values: &[*mut sqlite3_value],
     ) -> Result<()> {
-        let page: *mut PagePointer = unsafe { api::value_pointer(&values[0], b"wut\0").unwrap() };
+        let page = value_page(&values[0])?;
         unsafe {
             let o = (*page).1.objects().iter();
             self.document = Some((*page).0);
@@ -144,7 +142,7 @@ impl VTabCursor for PdfImagesCursor<'_> {
     fn next(&mut self) -> Result<()> {
         self.rowid += 1;
         loop {
-            self.current = self.iter.as_mut().unwrap().next();
+            self.current = self.iter.as_mut().and_then(|iter| iter.next());
             match self.current.as_ref() {
                 None => break,
                 Some(PdfPageObject::Image(_)) => break,
@@ -159,27 +157,33 @@ impl VTabCursor for PdfImagesCursor<'_> {
     }
 
     fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
-        let img = self.current.as_ref().unwrap().as_image_object().unwrap();
+        let img = self
+            .current
+            .as_ref()
+            .and_then(|object| object.as_image_object())
+            .ok_or_else(|| Error::new_message("pdf_images cursor has no current image"))?;
+        let bounds = || {
+            img.bounds()
+                .map_err(|_| Error::new_message("image object is missing a bounds box"))
+        };
         match column(i) {
-            Some(Columns::X) => {
-                api::result_double(context, img.bounds().unwrap().left.value.into())
+            Some(Columns::X) => api::result_double(context, bounds()?.left.value.into()),
+            Some(Columns::Y) => api::result_double(context, bounds()?.top.value.into()),
+            Some(Columns::Width) => {
+                api::result_double(context, img.width().map_err(pdfium_error)?.value.into())
             }
-            Some(Columns::Y) => api::result_double(context, img.bounds().unwrap().top.value.into()),
-            Some(Columns::Width) => api::result_double(context, img.width().unwrap().value.into()),
             Some(Columns::Height) => {
-                api::result_double(context, img.height().unwrap().value.into())
+                api::result_double(context, img.height().map_err(pdfium_error)?.value.into())
             }
             Some(Columns::Image) => {
+                let document = self
+                    .document
+                    .ok_or_else(|| Error::new_message("pdf_images cursor has no document"))?;
                 let i = img
-                    .get_processed_image(unsafe { &*self.document.unwrap() })
-                    .unwrap();
-                let mut c = std::io::Cursor::new(Vec::new());
-                i.write_to(&mut c, ImageOutputFormat::Png).unwrap();
-                let mut buffer = Vec::new();
-                c.seek(std::io::SeekFrom::Start(0)).unwrap();
-                c.read_to_end(&mut buffer).unwrap();
-
-                api::result_blob(context, buffer.as_slice());
+                    .get_processed_image(unsafe { &*document })
+                    .map_err(pdfium_error)?;
+
+                api::result_blob(context, encode_image(i, &OutputFormat::Png)?.as_slice());
             }
 
             Some(Columns::Page) => {
@@ -1,59 +1,284 @@
+// every table's best_index matches on the constrained column, even the ones that only
+// have a single hidden column to look for
+#![allow(clippy::single_match)]
+
+mod aggregate;
 mod annotations;
+mod attachments;
+mod bookmarks;
+mod edit;
+mod fonts;
+mod form_fields;
 mod images;
+mod links;
+mod merge;
+mod metadata;
+mod objects;
 mod pages;
-use std::io::{Read, Seek};
-
-use image::ImageOutputFormat;
+mod paths;
+mod raw;
+mod render;
+mod signatures;
+mod text_chars;
+mod text_search;
+mod text_segments;
+mod xmp;
+use image::ImageError;
 use pdfium_render::prelude::*;
 
-use sqlite_loadable::{api, define_scalar_function, Result};
+use sqlite_loadable::{api, define_scalar_function, Error, Result};
 use sqlite_loadable::{define_table_function, prelude::*};
 
-type PagePointer<'a> = (*const PdfDocument<'a>, PdfPage<'a>);
-pub fn pdf_page_thumbnail(
-    context: *mut sqlite3_context,
-    values: &[*mut sqlite3_value],
-) -> Result<()> {
-    let xx: *mut PagePointer = unsafe { api::value_pointer(&values[0], b"wut\0").unwrap() };
-
-    let cfg = PdfRenderConfig::new().thumbnail(256);
-    unsafe {
-        let bitmap = (*xx).1.render_with_config(&cfg).unwrap();
-        let mut c = std::io::Cursor::new(Vec::new());
-        bitmap
-            .as_image()
-            .write_to(&mut c, ImageOutputFormat::Png)
-            .unwrap();
-        let mut buffer = Vec::new();
-        c.seek(std::io::SeekFrom::Start(0)).unwrap();
-        c.read_to_end(&mut buffer).unwrap();
-
-        api::result_blob(context, buffer.as_slice());
+use std::{cell::OnceCell, sync::OnceLock};
+
+use crate::raw::RawPdf;
+
+/// The pdfium library, shared by every function and table of the extension. Dropping a
+/// `Pdfium` shuts down the whole library, even underneath documents that other queries
+/// still have open, so there's only ever this one instance and it's never dropped.
+pub(crate) fn pdfium() -> &'static Pdfium {
+    static PDFIUM: OnceLock<Pdfium> = OnceLock::new();
+    PDFIUM.get_or_init(Pdfium::default)
+}
+
+/// A page handed out by `pdf_pages`: its document, the page itself, its index and the
+/// document's raw reader, which `page_raw` saves the document for at most once.
+pub(crate) type PagePointer<'a> = (
+    *const PdfDocument<'a>,
+    PdfPage<'a>,
+    PdfPageIndex,
+    *const OnceCell<RawPdf<'static>>,
+);
+
+/// Reads a page pointer (from the `page` column of `pdf_pages`) out of the given value.
+pub(crate) fn value_page<'a>(value: &*mut sqlite3_value) -> Result<*mut PagePointer<'a>> {
+    unsafe { api::value_pointer(value, b"wut\0") }
+        .ok_or_else(|| Error::new_message("expected a page from the pdf_pages() page column"))
+}
+
+/// The raw reader of a page's document. Saving a document for the raw reader is
+/// expensive, so it's done once per document and shared by every page of it.
+pub(crate) fn page_raw<'a>(page: *mut PagePointer) -> Result<&'a RawPdf<'static>> {
+    let (document, _, _, raw) = unsafe { &*page };
+    let raw = unsafe { &**raw };
+    if let Some(raw) = raw.get() {
+        return Ok(raw);
     }
-    Ok(())
+    let read = RawPdf::from_document(unsafe { &**document })?;
+    Ok(raw.get_or_init(|| read))
+}
+
+/// Returns the argument at the given index, or `None` if it was not given or is NULL.
+pub(crate) fn value_arg(values: &[*mut sqlite3_value], i: usize) -> Option<&*mut sqlite3_value> {
+    values.get(i).filter(|value| !api::value_is_null(value))
+}
+
+/// Loads a PDF document from a blob, with a helpful error if it needs a password that
+/// wasn't given to the `function` table function.
+pub(crate) fn load_pdf<'a>(
+    pdfium: &'a Pdfium,
+    src: &'a [u8],
+    password: Option<&str>,
+    function: &str,
+) -> Result<PdfDocument<'a>> {
+    pdfium
+        .load_pdf_from_byte_slice(src, password)
+        .map_err(|err| match err {
+            PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::PasswordError) => {
+                match password {
+                    Some(_) => Error::new_message("incorrect password for encrypted PDF"),
+                    None => Error::new_message(format!(
+                        "PDF is encrypted, pass its password to {function}()"
+                    )),
+                }
+            }
+            err => pdfium_error(err),
+        })
 }
-pub fn pdf_page_xxx(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
-    let xx: *mut PagePointer = unsafe { api::value_pointer(&values[0], b"wut\0").unwrap() };
 
-    unsafe {
-        let p = Pdfium::default();
-        let x = p.create_new_pdf().unwrap();
-        //x.pages().copy_pages_from_document(source, pages, destination_page_index)
+/// Converts a pdfium error into a SQLite error with a human-readable message.
+pub(crate) fn pdfium_error(err: PdfiumError) -> Error {
+    let message = match err {
+        PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::FileError) => {
+            "PDF file could not be opened or read".to_owned()
+        }
+        PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::FormatError) => {
+            "PDF file is corrupt or not a PDF".to_owned()
+        }
+        PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::PasswordError) => {
+            "PDF file is encrypted and the password is missing or incorrect".to_owned()
+        }
+        PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::SecurityError) => {
+            "PDF file uses an unsupported security handler".to_owned()
+        }
+        PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::PageError) => {
+            "PDF page could not be found or is corrupt".to_owned()
+        }
+        PdfiumError::UnsupportedPdfPageObjectType
+        | PdfiumError::UnknownPdfPageObjectType
+        | PdfiumError::UnknownPdfAnnotationType
+        | PdfiumError::UnknownPdfColorSpace => format!("unsupported PDF feature: {err:?}"),
+        err => format!("pdfium error: {err:?}"),
+    };
+    Error::new_message(message)
+}
+
+/// Formats a color as `'#RRGGBB'`, or `'#AARRGGBB'` when it is not fully opaque.
+pub(crate) fn color_hex(color: &PdfColor) -> String {
+    if color.alpha() == 255 {
+        format!("#{}", color.to_hex())
+    } else {
+        format!("#{}", color.to_hex_with_alpha())
     }
-    Ok(())
 }
 
-#[sqlite_entrypoint]
-pub fn sqlite3_pdf_init(db: *mut sqlite3) -> Result<()> {
+/// Returns the numeric (100-900) value of a font weight.
+pub(crate) fn font_weight_value(weight: PdfFontWeight) -> u32 {
+    match weight {
+        PdfFontWeight::Weight100 => 100,
+        PdfFontWeight::Weight200 => 200,
+        PdfFontWeight::Weight300 => 300,
+        PdfFontWeight::Weight400Normal => 400,
+        PdfFontWeight::Weight500 => 500,
+        PdfFontWeight::Weight600 => 600,
+        PdfFontWeight::Weight700Bold => 700,
+        PdfFontWeight::Weight800 => 800,
+        PdfFontWeight::Weight900 => 900,
+        PdfFontWeight::Custom(weight) => weight,
+    }
+}
+
+/// The clockwise rotation in degrees, like the `rotation` column of `pdf_pages`.
+pub(crate) fn rotation_degrees(rotation: PdfPageRenderRotation) -> i64 {
+    match rotation {
+        PdfPageRenderRotation::None => 0,
+        PdfPageRenderRotation::Degrees90 => 90,
+        PdfPageRenderRotation::Degrees180 => 180,
+        PdfPageRenderRotation::Degrees270 => 270,
+    }
+}
+
+/// Converts an image encoding error into a SQLite error.
+pub(crate) fn image_error(err: ImageError) -> Error {
+    Error::new_message(format!("error encoding image: {err}"))
+}
+
+/// # Safety
+///
+/// Should only be called by SQLite, when loading the extension.
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_pdf_init(
+    db: *mut sqlite3,
+    pz_err_msg: *mut *mut c_char,
+    p_api: *mut sqlite3_api_routines,
+) -> c_uint {
+    // what #[sqlite_entrypoint] expands to, plus keeping a copy of the API routines:
+    // sqlite-loadable keeps its own copy private and has no sqlite3_aggregate_context(),
+    // which the aggregates in aggregate.rs need
+    aggregate::set_api_routines(p_api);
+    register_entrypoint(db, pz_err_msg, p_api, init)
+}
+
+fn init(db: *mut sqlite3) -> Result<()> {
     define_scalar_function(
         db,
         "pdf_page_thumbnail",
-        1,
-        pdf_page_thumbnail,
+        -1,
+        render::pdf_page_thumbnail,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_page_render",
+        -1,
+        render::pdf_page_render,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_page_text_in_rect",
+        5,
+        pages::pdf_page_text_in_rect,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_xmp",
+        -1,
+        xmp::pdf_xmp,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_xmp_json",
+        -1,
+        xmp::pdf_xmp_json,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_fill_form",
+        -1,
+        form_fields::pdf_fill_form,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_extract_pages",
+        -1,
+        merge::pdf_extract_pages,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_rotate_pages",
+        -1,
+        edit::pdf_rotate_pages,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_delete_pages",
+        -1,
+        edit::pdf_delete_pages,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    define_scalar_function(
+        db,
+        "pdf_reorder_pages",
+        -1,
+        edit::pdf_reorder_pages,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+    aggregate::define_aggregate_function::<merge::PdfMerge>(
+        db,
+        "pdf_merge",
+        -1,
         FunctionFlags::DETERMINISTIC,
     )?;
     define_table_function::<pages::PdfPagesTable>(db, "pdf_pages", None)?;
     define_table_function::<images::PdfImagesTable>(db, "pdf_images", None)?;
     define_table_function::<annotations::PdfAnnotationsTable>(db, "pdf_annotations", None)?;
+    define_table_function::<links::PdfLinksTable>(db, "pdf_links", None)?;
+    define_table_function::<objects::PdfObjectsTable>(db, "pdf_objects", None)?;
+    define_table_function::<paths::PdfPathsTable>(db, "pdf_paths", None)?;
+    define_table_function::<text_chars::PdfTextCharsTable>(db, "pdf_text_chars", None)?;
+    define_table_function::<text_segments::PdfTextSegmentsTable>(
+        db,
+        "pdf_text_words",
+        Some(text_segments::Grouping::Words),
+    )?;
+    define_table_function::<text_segments::PdfTextSegmentsTable>(
+        db,
+        "pdf_text_lines",
+        Some(text_segments::Grouping::Lines),
+    )?;
+    define_table_function::<text_search::PdfTextSearchTable>(db, "pdf_text_search", None)?;
+    define_table_function::<metadata::PdfMetadataTable>(db, "pdf_metadata", None)?;
+    define_table_function::<bookmarks::PdfBookmarksTable>(db, "pdf_bookmarks", None)?;
+    define_table_function::<form_fields::PdfFormFieldsTable>(db, "pdf_form_fields", None)?;
+    define_table_function::<fonts::PdfFontsTable>(db, "pdf_fonts", None)?;
+    define_table_function::<attachments::PdfAttachmentsTable>(db, "pdf_attachments", None)?;
+    define_table_function::<signatures::PdfSignaturesTable>(db, "pdf_signatures", None)?;
     Ok(())
 }
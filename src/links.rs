@@ -0,0 +1,275 @@
+use pdfium_render::{
+    action::PdfAction,
+    destination::{PdfDestination, PdfDestinationViewSettings},
+    link::PdfLink,
+    page_annotation::PdfPageAnnotationCommon,
+    rect::PdfRect,
+};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::value_page;
+
+static CREATE_SQL: &str = "CREATE TABLE x(x, y, width, height, action text, uri text, dest_page_index int, dest_x, dest_y, dest_zoom, page hidden)";
+enum Columns {
+    X,
+    Y,
+    Width,
+    Height,
+    Action,
+    Uri,
+    DestPageIndex,
+    DestX,
+    DestY,
+    DestZoom,
+    Page,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::X),
+        1 => Some(Columns::Y),
+        2 => Some(Columns::Width),
+        3 => Some(Columns::Height),
+        4 => Some(Columns::Action),
+        5 => Some(Columns::Uri),
+        6 => Some(Columns::DestPageIndex),
+        7 => Some(Columns::DestX),
+        8 => Some(Columns::DestY),
+        9 => Some(Columns::DestZoom),
+        10 => Some(Columns::Page),
+        _ => None,
+    }
+}
+
+/// Where an internal link points to: a page, and optionally where on that page
+/// and at what zoom.
+#[derive(Default)]
+struct LinkDestination {
+    page_index: Option<u16>,
+    x: Option<f32>,
+    y: Option<f32>,
+    zoom: Option<f32>,
+}
+
+impl LinkDestination {
+    fn from_pdfium(destination: &PdfDestination) -> LinkDestination {
+        let (x, y, zoom) = match destination.view_settings() {
+            Ok(PdfDestinationViewSettings::SpecificCoordinatesAndZoom(x, y, zoom)) => {
+                (x.map(|x| x.value), y.map(|y| y.value), zoom)
+            }
+            Ok(PdfDestinationViewSettings::FitPageHorizontallyToWindow(y))
+            | Ok(PdfDestinationViewSettings::FitBoundsHorizontallyToWindow(y)) => {
+                (None, y.map(|y| y.value), None)
+            }
+            Ok(PdfDestinationViewSettings::FitPageVerticallyToWindow(x))
+            | Ok(PdfDestinationViewSettings::FitBoundsVerticallyToWindow(x)) => {
+                (x.map(|x| x.value), None, None)
+            }
+            Ok(PdfDestinationViewSettings::FitPageToRectangle(rect)) => {
+                (Some(rect.left.value), Some(rect.top.value), None)
+            }
+            _ => (None, None, None),
+        };
+        LinkDestination {
+            page_index: destination.page_index().ok(),
+            x,
+            y,
+            // a zoom of 0 means "keep the current zoom"
+            zoom: zoom.filter(|zoom| *zoom > 0.0),
+        }
+    }
+}
+
+struct Link {
+    bounds: PdfRect,
+    action: Option<&'static str>,
+    uri: Option<String>,
+    destination: LinkDestination,
+}
+
+impl Link {
+    fn from_pdfium(bounds: PdfRect, link: &PdfLink) -> Link {
+        let action = link.action();
+        let destination = match (link.destination(), &action) {
+            (Some(destination), _) => LinkDestination::from_pdfium(&destination),
+            (None, Some(PdfAction::LocalDestination(action))) => match action.destination() {
+                Ok(destination) => LinkDestination::from_pdfium(&destination),
+                Err(_) => LinkDestination::default(),
+            },
+            (None, _) => LinkDestination::default(),
+        };
+        let action_type = match &action {
+            Some(PdfAction::LocalDestination(_)) => Some("goto"),
+            Some(PdfAction::RemoteDestination(_)) => Some("remote_goto"),
+            Some(PdfAction::EmbeddedDestination(_)) => Some("embedded_goto"),
+            Some(PdfAction::Launch(_)) => Some("launch"),
+            Some(PdfAction::Uri(_)) => Some("uri"),
+            Some(PdfAction::Unsupported(_)) => Some("unsupported"),
+            // links with a /Dest instead of an action behave like a GoTo action
+            None if destination.page_index.is_some() => Some("goto"),
+            None => None,
+        };
+        let uri = match &action {
+            Some(PdfAction::Uri(action)) => action.uri().ok(),
+            _ => None,
+        };
+        Link {
+            bounds,
+            action: action_type,
+            uri,
+            destination,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct PdfLinksTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfLinksTable {
+    type Aux = ();
+    type Cursor = PdfLinksCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfLinksTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfLinksTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_page = false;
+        for mut constraint in info.constraints() {
+            match column(constraint.column_idx()) {
+                Some(Columns::Page) => {
+                    if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                        constraint.set_omit(true);
+                        constraint.set_argv_index(1);
+                        has_page = true;
+                    } else {
+                        return Err(BestIndexError::Constraint);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !has_page {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfLinksCursor<'_>> {
+        Ok(PdfLinksCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfLinksCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    links: Vec<Link>,
+    phantom: PhantomData<&'vtab PdfLinksTable>,
+}
+impl PdfLinksCursor<'_> {
+    fn new<'vtab>() -> PdfLinksCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfLinksCursor {
+            base,
+            rowid: 0,
+            links: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfLinksCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let page = value_page(&values[0])?;
+        let annotations = unsafe { (*page).1.annotations() };
+        // links are read through their annotations, since only those know their rect
+        self.links = annotations
+            .iter()
+            .filter_map(|annotation| {
+                let link = annotation.as_link_annotation()?.link().ok()?;
+                let bounds = annotation.bounds().ok()?;
+                Some(Link::from_pdfium(bounds, &link))
+            })
+            .collect();
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.links.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let link = &self.links[self.rowid];
+        let result_optional = |value: Option<f32>| match value {
+            Some(value) => api::result_double(context, value.into()),
+            None => api::result_null(context),
+        };
+        match column(i) {
+            Some(Columns::X) => api::result_double(context, link.bounds.left.value.into()),
+            Some(Columns::Y) => api::result_double(context, link.bounds.top.value.into()),
+            Some(Columns::Width) => api::result_double(context, link.bounds.width().value.into()),
+            Some(Columns::Height) => api::result_double(context, link.bounds.height().value.into()),
+            Some(Columns::Action) => match link.action {
+                Some(action) => api::result_text(context, action)?,
+                None => api::result_null(context),
+            },
+            Some(Columns::Uri) => match &link.uri {
+                Some(uri) => api::result_text(context, uri)?,
+                None => api::result_null(context),
+            },
+            Some(Columns::DestPageIndex) => match link.destination.page_index {
+                Some(page_index) => api::result_int64(context, page_index.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::DestX) => result_optional(link.destination.x),
+            Some(Columns::DestY) => result_optional(link.destination.y),
+            Some(Columns::DestZoom) => result_optional(link.destination.zoom),
+            Some(Columns::Page) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
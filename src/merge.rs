@@ -0,0 +1,130 @@
+use pdfium_render::{document::PdfDocument, pages::PdfPageIndex};
+use sqlite_loadable::{api, prelude::*, Error, Result};
+
+use std::os::raw::c_int;
+
+use crate::{aggregate::Aggregate, load_pdf, pdfium, pdfium_error, value_arg, value_page};
+
+/// Parses a page range like `'1-3,7,10-'` into page indexes. Pages are numbered from 1,
+/// and a range without an end runs to the last page.
+pub(crate) fn parse_page_range(range: &str, page_count: PdfPageIndex) -> Result<Vec<PdfPageIndex>> {
+    let invalid = || Error::new_message(format!("invalid page range '{range}'"));
+    let page_number = |number: &str| -> Result<PdfPageIndex> {
+        let number: PdfPageIndex = number.trim().parse().map_err(|_| invalid())?;
+        if number == 0 || number > page_count {
+            return Err(Error::new_message(format!(
+                "page {number} is out of range, the PDF has {page_count} pages"
+            )));
+        }
+        Ok(number)
+    };
+    let mut pages = vec![];
+    for part in range.split(',') {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) if end.trim().is_empty() => (page_number(start)?, page_count),
+            Some((start, end)) => (page_number(start)?, page_number(end)?),
+            None => (page_number(part)?, page_number(part)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        pages.extend((start - 1)..end);
+    }
+    Ok(pages)
+}
+
+/// Copies the pages at the given indexes of `source` to the end of `destination`.
+pub(crate) fn import_pages(
+    destination: &PdfDocument,
+    source: &PdfDocument,
+    pages: &[PdfPageIndex],
+) -> Result<()> {
+    let bindings = destination.bindings();
+    let imported = bindings.FPDF_ImportPagesByIndex_vec(
+        bindings.get_handle_from_document(destination),
+        bindings.get_handle_from_document(source),
+        pages.iter().map(|page| *page as c_int).collect(),
+        destination.pages().len() as c_int,
+    );
+    if !bindings.is_true(imported) {
+        return Err(Error::new_message("pages could not be copied between PDFs"));
+    }
+    Ok(())
+}
+
+/// `pdf_extract_pages(pdf, page_range [, password])` returns a new PDF with just the given
+/// pages, and `pdf_extract_pages(page)` a single-page PDF of a page from `pdf_pages`.
+pub fn pdf_extract_pages(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    let extracted = pdfium().create_new_pdf().map_err(pdfium_error)?;
+    match value_page(&values[0]) {
+        Ok(page) => {
+            if values.len() != 1 {
+                return Err(Error::new_message(
+                    "pdf_extract_pages() takes no page range for a page from pdf_pages()",
+                ));
+            }
+            let (document, page_index) = unsafe { (&*(*page).0, (*page).2) };
+            import_pages(&extracted, document, &[page_index])?;
+        }
+        Err(_) => {
+            if values.len() != 2 && values.len() != 3 {
+                return Err(Error::new_message(
+                    "pdf_extract_pages() takes a PDF, a page range like '1-3,7,10-' and an optional password",
+                ));
+            }
+            let password = match value_arg(values, 2) {
+                Some(value) => Some(api::value_text(value)?),
+                None => None,
+            };
+            let src = api::value_blob(&values[0]);
+            let document = load_pdf(pdfium(), src, password, "pdf_extract_pages")?;
+            let pages = parse_page_range(api::value_text(&values[1])?, document.pages().len())?;
+            import_pages(&extracted, &document, &pages)?;
+        }
+    }
+    api::result_blob(context, &extracted.save_to_bytes().map_err(pdfium_error)?);
+    Ok(())
+}
+
+/// The state of one `pdf_merge(pdf [, page_range])` call, which concatenates the pages of
+/// every row's PDF into a single new PDF. Each row's pages are copied over as it arrives,
+/// so only the merged PDF is kept around.
+#[derive(Default)]
+pub(crate) struct PdfMerge {
+    merged: Option<PdfDocument<'static>>,
+}
+
+impl Aggregate for PdfMerge {
+    fn step(&mut self, values: &[*mut sqlite3_value]) -> Result<()> {
+        if values.is_empty() || values.len() > 2 {
+            return Err(Error::new_message("pdf_merge() takes 1 or 2 arguments"));
+        }
+        let merged = match &mut self.merged {
+            Some(merged) => merged,
+            None => self
+                .merged
+                .insert(pdfium().create_new_pdf().map_err(pdfium_error)?),
+        };
+        let document = load_pdf(pdfium(), api::value_blob(&values[0]), None, "pdf_merge")?;
+        let page_count = document.pages().len();
+        let pages = match value_arg(values, 1) {
+            Some(range) => parse_page_range(api::value_text(range)?, page_count)?,
+            None => (0..page_count).collect(),
+        };
+        import_pages(merged, &document, &pages)
+    }
+
+    fn finalize(self, context: *mut sqlite3_context) -> Result<()> {
+        match self.merged {
+            Some(merged) => {
+                api::result_blob(context, &merged.save_to_bytes().map_err(pdfium_error)?);
+            }
+            // merging no rows gives NULL, like sum() does
+            None => api::result_null(context),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+use pdfium_render::{
+    document::PdfDocumentVersion, metadata::PdfDocumentMetadataTagType,
+    permissions::PdfSecurityHandlerRevision,
+};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{
+    load_pdf, pdfium,
+    raw::{Object, RawPdf},
+    value_arg,
+};
+
+static CREATE_SQL: &str = "CREATE TABLE x(title text, author text, subject text, keywords text, creator text, producer text, creation_date text, modification_date text, version text, page_count int, linearized int, tagged int, encrypted int, pdf hidden, password hidden)";
+enum Columns {
+    Title,
+    Author,
+    Subject,
+    Keywords,
+    Creator,
+    Producer,
+    CreationDate,
+    ModificationDate,
+    Version,
+    PageCount,
+    Linearized,
+    Tagged,
+    Encrypted,
+    Pdf,
+    Password,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Title),
+        1 => Some(Columns::Author),
+        2 => Some(Columns::Subject),
+        3 => Some(Columns::Keywords),
+        4 => Some(Columns::Creator),
+        5 => Some(Columns::Producer),
+        6 => Some(Columns::CreationDate),
+        7 => Some(Columns::ModificationDate),
+        8 => Some(Columns::Version),
+        9 => Some(Columns::PageCount),
+        10 => Some(Columns::Linearized),
+        11 => Some(Columns::Tagged),
+        12 => Some(Columns::Encrypted),
+        13 => Some(Columns::Pdf),
+        14 => Some(Columns::Password),
+        _ => None,
+    }
+}
+
+/// Converts a PDF date string like `D:20230415093000+02'00'` into ISO-8601
+/// (`2023-04-15T09:30:00+02:00`). Everything after the year is optional in PDF dates,
+/// and missing parts default to the start of the year. Returns `None` if the date
+/// isn't in the PDF date format.
+pub(crate) fn pdf_date_iso8601(date: &str) -> Option<String> {
+    let date = date.trim();
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits = date
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(date.len());
+    let (datetime, offset) = date.split_at(digits);
+    if datetime.len() < 4 || datetime.len() > 14 || datetime.len() % 2 != 0 {
+        return None;
+    }
+    let part =
+        |start: usize, default: &'static str| datetime.get(start..start + 2).unwrap_or(default);
+    let mut iso = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &datetime[..4],
+        part(4, "01"),
+        part(6, "01"),
+        part(8, "00"),
+        part(10, "00"),
+        part(12, "00"),
+    );
+    // the offset is `Z`, or `+HH'mm'` / `-HH'mm'` with optional apostrophes and minutes
+    let offset: String = offset.chars().filter(|c| *c != '\'').collect();
+    match offset.as_bytes() {
+        [] => (),
+        [b'Z', ..] => iso.push('Z'),
+        [sign @ (b'+' | b'-'), rest @ ..]
+            if (rest.len() == 2 || rest.len() == 4) && rest.iter().all(u8::is_ascii_digit) =>
+        {
+            let (hours, minutes) = offset[1..].split_at(2);
+            let minutes = if minutes.is_empty() { "00" } else { minutes };
+            // PDF writes UTC as `+00'00'`, ISO-8601 prefers `Z`
+            if hours == "00" && minutes == "00" {
+                iso.push('Z');
+            } else {
+                iso.push_str(&format!("{}{hours}:{minutes}", *sign as char));
+            }
+        }
+        _ => return None,
+    }
+    Some(iso)
+}
+
+/// Formats a document version like `'1.7'`.
+fn version_string(version: PdfDocumentVersion) -> Option<String> {
+    let version = match version {
+        PdfDocumentVersion::Unset => return None,
+        PdfDocumentVersion::Pdf1_0 => 10,
+        PdfDocumentVersion::Pdf1_1 => 11,
+        PdfDocumentVersion::Pdf1_2 => 12,
+        PdfDocumentVersion::Pdf1_3 => 13,
+        PdfDocumentVersion::Pdf1_4 => 14,
+        PdfDocumentVersion::Pdf1_5 => 15,
+        PdfDocumentVersion::Pdf1_6 => 16,
+        PdfDocumentVersion::Pdf1_7 => 17,
+        PdfDocumentVersion::Pdf2_0 => 20,
+        PdfDocumentVersion::Other(version) => version,
+    };
+    Some(format!("{}.{}", version / 10, version % 10))
+}
+
+/// Whether the PDF is linearized ("fast web view"). The linearization dictionary
+/// has to be the first object in the file, within its first 1024 bytes.
+fn is_linearized(src: &[u8]) -> bool {
+    let head = &src[..src.len().min(1024)];
+    head.windows(b"/Linearized".len())
+        .any(|window| window == b"/Linearized")
+}
+
+/// Whether the PDF is tagged, i.e. the `/MarkInfo` dictionary of its catalog says it's
+/// `/Marked`, or `None` when the catalog can't be read.
+fn is_tagged(raw: &RawPdf) -> Option<bool> {
+    let catalog = raw.catalog()?;
+    let Some(mark_info) = catalog.get(b"MarkInfo") else {
+        return Some(false);
+    };
+    let Some(marked) = raw.resolve(mark_info)?.get(b"Marked").cloned() else {
+        return Some(false);
+    };
+    Some(matches!(*raw.resolve(&marked)?, Object::Boolean(true)))
+}
+
+/// The metadata of a single PDF, read eagerly so the document can be closed in filter.
+struct Metadata {
+    tags: Vec<(PdfDocumentMetadataTagType, String)>,
+    version: Option<String>,
+    page_count: u16,
+    linearized: bool,
+    tagged: Option<bool>,
+    encrypted: bool,
+}
+
+impl Metadata {
+    fn tag(&self, tag_type: PdfDocumentMetadataTagType) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(t, _)| *t == tag_type)
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[repr(C)]
+pub struct PdfMetadataTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfMetadataTable {
+    type Aux = ();
+    type Cursor = PdfMetadataCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfMetadataTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfMetadataTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_pdf = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Pdf) => {
+                    has_pdf = true;
+                    1
+                }
+                Some(Columns::Password) => 2,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_pdf {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(1);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfMetadataCursor<'_>> {
+        Ok(PdfMetadataCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfMetadataCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: i64,
+    metadata: Option<Metadata>,
+    phantom: PhantomData<&'vtab PdfMetadataTable>,
+}
+impl PdfMetadataCursor<'_> {
+    fn new<'vtab>() -> PdfMetadataCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfMetadataCursor {
+            base,
+            rowid: 0,
+            metadata: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfMetadataCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let src = api::value_blob(&values[0]);
+        let password = match value_arg(values, 1) {
+            Some(value) => Some(api::value_text(value)?),
+            None => None,
+        };
+        let document = load_pdf(pdfium(), src, password, "pdf_metadata")?;
+        self.metadata = Some(Metadata {
+            tags: document
+                .metadata()
+                .iter()
+                .map(|tag| (tag.tag_type(), tag.value().to_owned()))
+                .collect(),
+            version: version_string(document.version()),
+            page_count: document.pages().len(),
+            linearized: is_linearized(src),
+            // the catalog can be read straight from the file, unless it's compressed in an
+            // object stream and only pdfium can get at it
+            tagged: is_tagged(&RawPdf::new(src))
+                .or_else(|| is_tagged(&RawPdf::from_document(&document).ok()?)),
+            encrypted: !matches!(
+                document.permissions().security_handler_revision(),
+                Ok(PdfSecurityHandlerRevision::Unprotected)
+            ),
+        });
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.metadata.is_none() || self.rowid >= 1
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let metadata = match self.metadata.as_ref() {
+            Some(metadata) => metadata,
+            None => return Ok(()),
+        };
+        let result_tag = |tag_type| match metadata.tag(tag_type) {
+            Some(value) => api::result_text(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        let result_date = |tag_type| match metadata.tag(tag_type) {
+            // dates that don't follow the PDF date format are passed through as-is
+            Some(date) => api::result_text(
+                context,
+                pdf_date_iso8601(date).unwrap_or_else(|| date.to_owned()),
+            ),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        match column(i) {
+            Some(Columns::Title) => result_tag(PdfDocumentMetadataTagType::Title)?,
+            Some(Columns::Author) => result_tag(PdfDocumentMetadataTagType::Author)?,
+            Some(Columns::Subject) => result_tag(PdfDocumentMetadataTagType::Subject)?,
+            Some(Columns::Keywords) => result_tag(PdfDocumentMetadataTagType::Keywords)?,
+            Some(Columns::Creator) => result_tag(PdfDocumentMetadataTagType::Creator)?,
+            Some(Columns::Producer) => result_tag(PdfDocumentMetadataTagType::Producer)?,
+            Some(Columns::CreationDate) => result_date(PdfDocumentMetadataTagType::CreationDate)?,
+            Some(Columns::ModificationDate) => {
+                result_date(PdfDocumentMetadataTagType::ModificationDate)?
+            }
+            Some(Columns::Version) => match &metadata.version {
+                Some(version) => api::result_text(context, version)?,
+                None => api::result_null(context),
+            },
+            Some(Columns::PageCount) => api::result_int64(context, metadata.page_count.into()),
+            Some(Columns::Linearized) => api::result_bool(context, metadata.linearized),
+            Some(Columns::Tagged) => match metadata.tagged {
+                Some(tagged) => api::result_bool(context, tagged),
+                None => api::result_null(context),
+            },
+            Some(Columns::Encrypted) => api::result_bool(context, metadata.encrypted),
+            Some(Columns::Pdf) | Some(Columns::Password) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_mark_info() {
+        let tagged = |catalog: &str| {
+            let src = format!("1 0 obj {catalog} endobj\n2 0 obj << /Marked true >> endobj\ntrailer << /Root 1 0 R >>\n");
+            is_tagged(&RawPdf::new(src.into_bytes()))
+        };
+        assert_eq!(
+            tagged("<< /Type /Catalog /MarkInfo << /Marked true >> >>"),
+            Some(true)
+        );
+        assert_eq!(tagged("<< /Type /Catalog /MarkInfo 2 0 R >>"), Some(true));
+        assert_eq!(
+            tagged("<< /Type /Catalog /MarkInfo << /Marked false >> >>"),
+            Some(false)
+        );
+        assert_eq!(tagged("<< /Type /Catalog >>"), Some(false));
+        // a MarkInfo that can't be found isn't taken as untagged
+        assert_eq!(tagged("<< /Type /Catalog /MarkInfo 9 0 R >>"), None);
+    }
+}
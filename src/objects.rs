@@ -0,0 +1,401 @@
+use pdfium_render::{
+    page_object::{PdfPageObject, PdfPageObjectCommon},
+    page_objects_common::PdfPageObjectsCommon,
+};
+use serde_json::json;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{
+    color_hex, pdfium_error,
+    raw::{content_operations, Object, RawPdf},
+    value_page,
+};
+
+static CREATE_SQL: &str = "CREATE TABLE x(kind text, x, y, width, height, matrix, fill_color text, stroke_color text, blend_mode text, text text, font_name text, font_size, page hidden)";
+enum Columns {
+    Kind,
+    X,
+    Y,
+    Width,
+    Height,
+    Matrix,
+    FillColor,
+    StrokeColor,
+    BlendMode,
+    Text,
+    FontName,
+    FontSize,
+    Page,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Kind),
+        1 => Some(Columns::X),
+        2 => Some(Columns::Y),
+        3 => Some(Columns::Width),
+        4 => Some(Columns::Height),
+        5 => Some(Columns::Matrix),
+        6 => Some(Columns::FillColor),
+        7 => Some(Columns::StrokeColor),
+        8 => Some(Columns::BlendMode),
+        9 => Some(Columns::Text),
+        10 => Some(Columns::FontName),
+        11 => Some(Columns::FontSize),
+        12 => Some(Columns::Page),
+        _ => None,
+    }
+}
+
+fn object_kind(object: &PdfPageObject) -> &'static str {
+    match object {
+        PdfPageObject::Text(_) => "text",
+        PdfPageObject::Path(_) => "path",
+        PdfPageObject::Image(_) => "image",
+        PdfPageObject::Shading(_) => "shading",
+        PdfPageObject::XObjectForm(_) => "form",
+        PdfPageObject::Unsupported(_) => "unsupported",
+    }
+}
+
+/// The value of an ExtGState's `/BM` entry. Arrays list blend modes in order of
+/// preference, and pdfium only looks at the first one.
+fn blend_mode_name(value: &Object) -> &'static str {
+    let name = match value {
+        Object::Array(names) => names.first().and_then(Object::as_name),
+        value => value.as_name(),
+    };
+    match name.unwrap_or_default() {
+        b"Multiply" => "multiply",
+        b"Screen" => "screen",
+        b"Overlay" => "overlay",
+        b"Darken" => "darken",
+        b"Lighten" => "lighten",
+        b"ColorDodge" => "color_dodge",
+        b"ColorBurn" => "color_burn",
+        b"HardLight" => "hard_light",
+        b"SoftLight" => "soft_light",
+        b"Difference" => "difference",
+        b"Exclusion" => "exclusion",
+        b"Hue" => "hue",
+        b"Saturation" => "saturation",
+        b"Color" => "color",
+        b"Luminosity" => "luminosity",
+        // `/Compatible` and unknown blend modes are treated as normal
+        _ => "normal",
+    }
+}
+
+/// The blend mode in effect for each object that the page's content stream paints.
+///
+/// pdfium can set an object's blend mode but not read it back, so this replays the
+/// `q`/`Q`/`gs` operators of the raw content stream, creating objects where pdfium's
+/// content parser would. The objects come out in the same order as `page.objects()`.
+fn content_blend_modes(raw: &RawPdf, page_index: usize) -> Option<Vec<&'static str>> {
+    let page = raw.page_dictionary(page_index)?;
+    let contents = raw.page_contents(&page)?;
+    let resources = raw.resolve(page.get(b"Resources")?)?.into_owned();
+    let resource = |category: &[u8], name: Option<&Object>| {
+        let resources = raw.resolve(resources.get(category)?)?;
+        Some(raw.resolve(resources.get(name?.as_name()?)?)?.into_owned())
+    };
+
+    let mut modes = vec![];
+    // the blend mode, and whether a font has been set for showing text
+    let mut state = ("normal", false);
+    let mut saved_states = vec![];
+    let mut has_path = false;
+    for (operator, operands) in content_operations(&contents) {
+        let creates_object = match operator.as_slice() {
+            b"q" => {
+                saved_states.push(state);
+                false
+            }
+            b"Q" => {
+                state = saved_states.pop().unwrap_or(state);
+                false
+            }
+            b"gs" => {
+                if let Some(ext_g_state) = resource(b"ExtGState", operands.first()) {
+                    if let Some(mode) = ext_g_state.get(b"BM") {
+                        state.0 = blend_mode_name(mode);
+                    }
+                }
+                false
+            }
+            b"Tf" => {
+                state.1 = true;
+                false
+            }
+            b"m" | b"l" | b"c" | b"v" | b"y" | b"re" => {
+                has_path = true;
+                false
+            }
+            // clipping paths end with `n`, which paints nothing
+            b"n" => {
+                has_path = false;
+                false
+            }
+            b"f" | b"F" | b"f*" | b"S" | b"s" | b"B" | b"B*" | b"b" | b"b*" => {
+                mem::take(&mut has_path)
+            }
+            // text objects are only created for non-empty strings
+            b"Tj" | b"'" | b"\"" => {
+                state.1 && matches!(operands.last(), Some(Object::String(s)) if !s.is_empty())
+            }
+            b"TJ" => {
+                state.1
+                    && operands
+                        .first()
+                        .and_then(Object::as_array)
+                        .unwrap_or_default()
+                        .iter()
+                        .any(|item| matches!(item, Object::String(s) if !s.is_empty()))
+            }
+            b"BI" | b"sh" => true,
+            b"Do" => resource(b"XObject", operands.first())
+                .and_then(|xobject| xobject.get(b"Subtype")?.as_name().map(<[u8]>::to_vec))
+                .is_some_and(|subtype| subtype == b"Image" || subtype == b"Form"),
+            _ => false,
+        };
+        if creates_object {
+            modes.push(state.0);
+        }
+    }
+    Some(modes)
+}
+
+#[repr(C)]
+pub struct PdfObjectsTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfObjectsTable {
+    type Aux = ();
+    type Cursor = PdfObjectsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfObjectsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfObjectsTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_page = false;
+        for mut constraint in info.constraints() {
+            match column(constraint.column_idx()) {
+                Some(Columns::Page) => {
+                    if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                        constraint.set_omit(true);
+                        constraint.set_argv_index(1);
+                        has_page = true;
+                    } else {
+                        return Err(BestIndexError::Constraint);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !has_page {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfObjectsCursor<'_>> {
+        Ok(PdfObjectsCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfObjectsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    objects: Vec<PdfPageObject<'vtab>>,
+    /// `None` when the blend mode of a transparent object couldn't be worked out
+    blend_modes: Vec<Option<&'static str>>,
+    phantom: PhantomData<&'vtab PdfObjectsTable>,
+}
+impl PdfObjectsCursor<'_> {
+    fn new<'vtab>() -> PdfObjectsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfObjectsCursor {
+            base,
+            rowid: 0,
+            objects: vec![],
+            blend_modes: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfObjectsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let page = value_page(&values[0])?;
+        let (document, objects, page_index) = unsafe {
+            (
+                &*(*page).0,
+                (*page).1.objects().iter().collect::<Vec<_>>(),
+                (*page).2,
+            )
+        };
+        // objects without any transparency are always blended normally, so the content
+        // stream only needs to be read when a page has transparent objects
+        let content_modes = if objects.iter().any(|object| object.has_transparency()) {
+            content_blend_modes(&RawPdf::from_page(document, page_index)?, 0).filter(|modes| {
+                // if the replayed objects don't line up with pdfium's, none of them are trusted
+                modes.len() == objects.len()
+                    && objects
+                        .iter()
+                        .zip(modes)
+                        .all(|(object, mode)| object.has_transparency() || *mode == "normal")
+            })
+        } else {
+            None
+        };
+        self.blend_modes = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| match &content_modes {
+                _ if !object.has_transparency() => Some("normal"),
+                Some(modes) => Some(modes[index]),
+                None => None,
+            })
+            .collect();
+        self.objects = objects;
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.objects.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let object = &self.objects[self.rowid];
+        let text = object.as_text_object();
+        let bounds = object.bounds();
+        let result_bound = |value: Option<f32>| match value {
+            Some(value) => api::result_double(context, value.into()),
+            None => api::result_null(context),
+        };
+        match column(i) {
+            Some(Columns::Kind) => api::result_text(context, object_kind(object))?,
+            Some(Columns::X) => result_bound(bounds.as_ref().ok().map(|b| b.left.value)),
+            Some(Columns::Y) => result_bound(bounds.as_ref().ok().map(|b| b.top.value)),
+            Some(Columns::Width) => result_bound(bounds.as_ref().ok().map(|b| b.width().value)),
+            Some(Columns::Height) => result_bound(bounds.as_ref().ok().map(|b| b.height().value)),
+            Some(Columns::Matrix) => {
+                let matrix = object.matrix().map_err(pdfium_error)?;
+                api::result_json(
+                    context,
+                    json!([
+                        matrix.a(),
+                        matrix.b(),
+                        matrix.c(),
+                        matrix.d(),
+                        matrix.e(),
+                        matrix.f()
+                    ]),
+                )?
+            }
+            Some(Columns::FillColor) => match object.fill_color() {
+                Ok(color) => api::result_text(context, color_hex(&color))?,
+                Err(_) => api::result_null(context),
+            },
+            Some(Columns::StrokeColor) => match object.stroke_color() {
+                Ok(color) => api::result_text(context, color_hex(&color))?,
+                Err(_) => api::result_null(context),
+            },
+            Some(Columns::BlendMode) => match self.blend_modes[self.rowid] {
+                Some(blend_mode) => api::result_text(context, blend_mode)?,
+                None => api::result_null(context),
+            },
+            Some(Columns::Text) => match text {
+                Some(text) => api::result_text(context, text.text())?,
+                None => api::result_null(context),
+            },
+            Some(Columns::FontName) => match text {
+                Some(text) => api::result_text(context, text.font().name())?,
+                None => api::result_null(context),
+            },
+            Some(Columns::FontSize) => match text {
+                Some(text) => api::result_double(context, text.scaled_font_size().value.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Page) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_blend_modes() {
+        let content = b"q /GS1 gs 0 0 10 10 re f Q 0 0 m 5 5 l S
+BT (no font) Tj /F1 12 Tf (Hi) Tj () Tj [() -20] TJ ET
+/GS2 gs /Im1 Do 0 0 10 10 re W n /Fm1 Do";
+        let src = format!(
+            "1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj
+3 0 obj << /Type /Page /Contents 4 0 R /Resources <<
+  /ExtGState << /GS1 << /BM /Multiply >> /GS2 5 0 R >>
+  /XObject << /Im1 << /Subtype /Image >> /Fm1 << /Subtype /Form >> >>
+>> >> endobj
+4 0 obj << /Length {} >> stream
+{}
+endstream endobj
+5 0 obj << /BM [/Screen /Normal] >> endobj
+trailer << /Root 1 0 R >>
+",
+            content.len(),
+            std::str::from_utf8(content).unwrap()
+        );
+        let raw = RawPdf::new(src.into_bytes());
+        assert_eq!(
+            content_blend_modes(&raw, 0).unwrap(),
+            ["multiply", "normal", "normal", "screen", "screen"]
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use pdfium_render::{document::PdfDocument, pages::PdfPages, pdfium::Pdfium};
+use pdfium_render::{
+    document::PdfDocument, error::PdfiumError, page_boundaries::PdfPageBoundaryBox,
+    page_objects_common::PdfPageObjectsCommon, pages::PdfPages, pdfium::Pdfium, rect::PdfRect,
+};
+use serde_json::json;
 use sqlite_loadable::{
     api,
     table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
@@ -6,17 +10,54 @@ use sqlite_loadable::{
 };
 use sqlite_loadable::{prelude::*, Error};
 
-use std::{marker::PhantomData, mem, os::raw::c_int};
+use std::{cell::OnceCell, cmp::Ordering, marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{load_pdf, pdfium, pdfium_error, raw::RawPdf, rotation_degrees, value_arg, value_page};
+
+/// pdf_page_text_in_rect(page, left, top, right, bottom)
+///
+/// Coordinates are in points from the bottom-left corner of the page, the same as
+/// the `x`/`y` columns of pdf_images.
+pub fn pdf_page_text_in_rect(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    let page = value_page(&values[0])?;
+    let left = api::value_double(&values[1]) as f32;
+    let top = api::value_double(&values[2]) as f32;
+    let right = api::value_double(&values[3]) as f32;
+    let bottom = api::value_double(&values[4]) as f32;
+    if left > right || bottom > top {
+        return Err(Error::new_message(
+            "pdf_page_text_in_rect() expects left <= right and bottom <= top",
+        ));
+    }
+    let text = unsafe { (*page).1.text() }.map_err(pdfium_error)?;
+    let rect = PdfRect::new_from_values(bottom, left, top, right);
+    api::result_text(context, text.inside_rect(rect))?;
+    Ok(())
+}
 
 static CREATE_SQL: &str =
-    "CREATE TABLE x(width int, height int, label text, full_text text, page, pdf hidden)";
+    "CREATE TABLE x(width int, height int, label text, full_text text, rotation int, orientation text, media_box, crop_box, bleed_box, trim_box, art_box, has_transparency int, object_count int, annotation_count int, page, pdf hidden, password hidden)";
 enum Columns {
     Width,
     Height,
     FullText,
     Label,
+    Rotation,
+    Orientation,
+    MediaBox,
+    CropBox,
+    BleedBox,
+    TrimBox,
+    ArtBox,
+    HasTransparency,
+    ObjectCount,
+    AnnotationCount,
     Page,
     Pdf,
+    Password,
 }
 fn column(index: i32) -> Option<Columns> {
     match index {
@@ -24,17 +65,63 @@ fn column(index: i32) -> Option<Columns> {
         1 => Some(Columns::Height),
         2 => Some(Columns::Label),
         3 => Some(Columns::FullText),
-        4 => Some(Columns::Page),
-        5 => Some(Columns::Pdf),
+        4 => Some(Columns::Rotation),
+        5 => Some(Columns::Orientation),
+        6 => Some(Columns::MediaBox),
+        7 => Some(Columns::CropBox),
+        8 => Some(Columns::BleedBox),
+        9 => Some(Columns::TrimBox),
+        10 => Some(Columns::ArtBox),
+        11 => Some(Columns::HasTransparency),
+        12 => Some(Columns::ObjectCount),
+        13 => Some(Columns::AnnotationCount),
+        14 => Some(Columns::Page),
+        15 => Some(Columns::Pdf),
+        16 => Some(Columns::Password),
         _ => None,
     }
 }
 
+/// `'portrait'` or `'landscape'` for a page of the given (rotated) size, or `None` for a
+/// square page, which is neither.
+fn orientation(width: f32, height: f32) -> Option<&'static str> {
+    match width.partial_cmp(&height)? {
+        Ordering::Less => Some("portrait"),
+        Ordering::Greater => Some("landscape"),
+        Ordering::Equal => None,
+    }
+}
+
+/// A page boundary box as a JSON array of `[left, bottom, right, top]`, like a PDF
+/// rectangle, or NULL when the page doesn't define that box.
+fn result_boundary_box(
+    context: *mut sqlite3_context,
+    boundary_box: std::result::Result<PdfPageBoundaryBox, PdfiumError>,
+) -> Result<()> {
+    match boundary_box {
+        Ok(boundary_box) => {
+            let bounds = boundary_box.bounds;
+            api::result_json(
+                context,
+                json!([
+                    bounds.left.value,
+                    bounds.bottom.value,
+                    bounds.right.value,
+                    bounds.top.value
+                ]),
+            )
+        }
+        Err(_) => {
+            api::result_null(context);
+            Ok(())
+        }
+    }
+}
+
 #[repr(C)]
 pub struct PdfPagesTable {
     /// must be first
     base: sqlite3_vtab,
-    pdfium: Pdfium,
 }
 
 impl<'vtab> VTab<'vtab> for PdfPagesTable {
@@ -47,10 +134,7 @@ impl<'vtab> VTab<'vtab> for PdfPagesTable {
         _args: VTabArguments,
     ) -> Result<(String, PdfPagesTable)> {
         let base: sqlite3_vtab = unsafe { mem::zeroed() };
-        let vtab = PdfPagesTable {
-            base,
-            pdfium: Pdfium::default(),
-        };
+        let vtab = PdfPagesTable { base };
         // TODO db.config(VTabConfig::Innocuous)?;
         Ok((CREATE_SQL.to_owned(), vtab))
     }
@@ -71,6 +155,14 @@ impl<'vtab> VTab<'vtab> for PdfPagesTable {
                         return Err(BestIndexError::Constraint);
                     }
                 }
+                Some(Columns::Password) => {
+                    if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                        constraint.set_omit(true);
+                        constraint.set_argv_index(2);
+                    } else {
+                        return Err(BestIndexError::Constraint);
+                    }
+                }
                 _ => (),
             }
         }
@@ -85,7 +177,7 @@ impl<'vtab> VTab<'vtab> for PdfPagesTable {
     }
 
     fn open(&mut self) -> Result<PdfPagesCursor<'_>> {
-        Ok(PdfPagesCursor::new(&self.pdfium))
+        Ok(PdfPagesCursor::new())
     }
 }
 
@@ -93,20 +185,21 @@ impl<'vtab> VTab<'vtab> for PdfPagesTable {
 pub struct PdfPagesCursor<'vtab> {
     /// Base class. Must be first
     base: sqlite3_vtab_cursor,
-    pdfium: &'vtab Pdfium,
     rowid: u16,
     pdf_document: Option<PdfDocument<'vtab>>,
+    /// The raw reader of the document, shared by the pages handed out in the page column
+    raw: OnceCell<RawPdf<'static>>,
     pdf_pages: Option<&'vtab PdfPages<'vtab>>,
     phantom: PhantomData<&'vtab PdfPagesTable>,
 }
 impl PdfPagesCursor<'_> {
-    fn new(pdfium: &Pdfium) -> PdfPagesCursor<'_> {
+    fn new<'vtab>() -> PdfPagesCursor<'vtab> {
         let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
         PdfPagesCursor {
             base,
-            pdfium,
             rowid: 0,
             pdf_document: None,
+            raw: OnceCell::new(),
             pdf_pages: None,
             phantom: PhantomData,
         }
@@ -121,10 +214,15 @@ impl VTabCursor for PdfPagesCursor<'_> {
         values: &[*mut sqlite3_value],
     ) -> Result<()> {
         let src = api::value_blob(&values[0]);
-        let pdf = self.pdfium.load_pdf_from_byte_slice(src, None).unwrap();
+        let password = match value_arg(values, 1) {
+            Some(value) => Some(api::value_text(value)?),
+            None => None,
+        };
+        let pdf = load_pdf(pdfium(), src, password, "pdf_pages")?;
         //self.pdf_pages = Some(pages);
         self.rowid = 0;
         self.pdf_document = Some(pdf);
+        self.raw = OnceCell::new();
 
         Ok(())
     }
@@ -135,17 +233,18 @@ impl VTabCursor for PdfPagesCursor<'_> {
     }
 
     fn eof(&self) -> bool {
-        self.rowid >= self.pdf_document.as_ref().unwrap().pages().len()
+        match self.pdf_document.as_ref() {
+            Some(document) => self.rowid >= document.pages().len(),
+            None => true,
+        }
     }
 
     fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
-        let page = self
+        let document = self
             .pdf_document
             .as_ref()
-            .unwrap()
-            .pages()
-            .get(self.rowid)
-            .unwrap();
+            .ok_or_else(|| Error::new_message("pdf_pages cursor has no document"))?;
+        let page = document.pages().get(self.rowid).map_err(pdfium_error)?;
         match column(i) {
             Some(Columns::Width) => {
                 api::result_double(context, page.width().value.into());
@@ -158,19 +257,47 @@ impl VTabCursor for PdfPagesCursor<'_> {
                 None => api::result_null(context),
             },
             Some(Columns::FullText) => {
-                api::result_text(context, page.text().unwrap().all())?;
+                api::result_text(context, page.text().map_err(pdfium_error)?.all())?;
+            }
+            Some(Columns::Rotation) => {
+                api::result_int64(
+                    context,
+                    rotation_degrees(page.rotation().map_err(pdfium_error)?),
+                );
+            }
+            Some(Columns::Orientation) => {
+                match orientation(page.width().value, page.height().value) {
+                    Some(orientation) => api::result_text(context, orientation)?,
+                    None => api::result_null(context),
+                }
+            }
+            Some(Columns::MediaBox) => result_boundary_box(context, page.boundaries().media())?,
+            Some(Columns::CropBox) => result_boundary_box(context, page.boundaries().crop())?,
+            Some(Columns::BleedBox) => result_boundary_box(context, page.boundaries().bleed())?,
+            Some(Columns::TrimBox) => result_boundary_box(context, page.boundaries().trim())?,
+            Some(Columns::ArtBox) => result_boundary_box(context, page.boundaries().art())?,
+            Some(Columns::HasTransparency) => {
+                api::result_bool(context, page.has_transparency());
+            }
+            Some(Columns::ObjectCount) => {
+                api::result_int64(context, page.objects().len() as i64);
+            }
+            Some(Columns::AnnotationCount) => {
+                api::result_int64(context, page.annotations().len() as i64);
             }
             Some(Columns::Page) => {
                 api::result_pointer(
                     context,
                     b"wut\0",
                     (
-                        self.pdf_document.as_ref().unwrap() as *const PdfDocument,
+                        document as *const PdfDocument,
                         page,
+                        self.rowid,
+                        &self.raw as *const OnceCell<RawPdf>,
                     ),
                 );
             }
-            Some(Columns::Pdf) => {
+            Some(Columns::Pdf) | Some(Columns::Password) => {
                 api::result_null(context);
             }
             None => (),
@@ -182,3 +309,16 @@ impl VTabCursor for PdfPagesCursor<'_> {
         Ok(self.rowid.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orients_pages() {
+        assert_eq!(orientation(595.0, 842.0), Some("portrait"));
+        assert_eq!(orientation(842.0, 595.0), Some("landscape"));
+        // square pages are neither
+        assert_eq!(orientation(612.0, 612.0), None);
+    }
+}
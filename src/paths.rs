@@ -0,0 +1,273 @@
+use pdfium_render::{
+    page_object::{PdfPageObject, PdfPageObjectCommon},
+    page_object_path::{PdfPagePathObject, PdfPathFillMode},
+    page_objects_common::PdfPageObjectsCommon,
+    path_segment::PdfPathSegmentType,
+    path_segments::PdfPathSegments,
+    rect::PdfRect,
+};
+use serde_json::{json, Value};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{color_hex, pdfium_error, value_page};
+
+static CREATE_SQL: &str = "CREATE TABLE x(object_index int, x, y, width, height, segments, stroke_width, stroke_color text, fill_color text, stroked int, filled int, page hidden)";
+enum Columns {
+    ObjectIndex,
+    X,
+    Y,
+    Width,
+    Height,
+    Segments,
+    StrokeWidth,
+    StrokeColor,
+    FillColor,
+    Stroked,
+    Filled,
+    Page,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::ObjectIndex),
+        1 => Some(Columns::X),
+        2 => Some(Columns::Y),
+        3 => Some(Columns::Width),
+        4 => Some(Columns::Height),
+        5 => Some(Columns::Segments),
+        6 => Some(Columns::StrokeWidth),
+        7 => Some(Columns::StrokeColor),
+        8 => Some(Columns::FillColor),
+        9 => Some(Columns::Stroked),
+        10 => Some(Columns::Filled),
+        11 => Some(Columns::Page),
+        _ => None,
+    }
+}
+
+struct Path {
+    /// The index of the path in the page's objects, the rowid of `pdf_objects`
+    object_index: usize,
+    bounds: Option<PdfRect>,
+    segments: Value,
+    stroke_width: Option<f32>,
+    stroke_color: Option<String>,
+    fill_color: Option<String>,
+    stroked: bool,
+    filled: bool,
+}
+
+/// The segments of a path in page coordinates, as JSON objects like
+/// `{"type": "line", "x": 10, "y": 20}`.
+///
+/// pdfium lists each of a cubic Bézier curve's two control points and end point as a
+/// separate segment, so those are joined back together into one `bezier` segment.
+fn segments_json(path: &PdfPagePathObject) -> Result<Value> {
+    let segments = path
+        .segments()
+        .transform(path.matrix().map_err(pdfium_error)?);
+    let mut result = vec![];
+    let mut control_points = vec![];
+    for segment in segments.iter() {
+        let (x, y) = (segment.x().value, segment.y().value);
+        match segment.segment_type() {
+            PdfPathSegmentType::MoveTo => result.push(json!({"type": "move", "x": x, "y": y})),
+            PdfPathSegmentType::LineTo => result.push(json!({"type": "line", "x": x, "y": y})),
+            PdfPathSegmentType::BezierTo => {
+                control_points.push([x, y]);
+                if let [[x1, y1], [x2, y2], [x, y]] = control_points[..] {
+                    result.push(json!({
+                        "type": "bezier",
+                        "x1": x1,
+                        "y1": y1,
+                        "x2": x2,
+                        "y2": y2,
+                        "x": x,
+                        "y": y,
+                    }));
+                    control_points.clear();
+                }
+            }
+            PdfPathSegmentType::Unknown => continue,
+        }
+        if segment.is_close() {
+            result.push(json!({"type": "close"}));
+        }
+    }
+    Ok(Value::Array(result))
+}
+
+#[repr(C)]
+pub struct PdfPathsTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfPathsTable {
+    type Aux = ();
+    type Cursor = PdfPathsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfPathsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfPathsTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_page = false;
+        for mut constraint in info.constraints() {
+            match column(constraint.column_idx()) {
+                Some(Columns::Page) => {
+                    if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                        constraint.set_omit(true);
+                        constraint.set_argv_index(1);
+                        has_page = true;
+                    } else {
+                        return Err(BestIndexError::Constraint);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !has_page {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfPathsCursor<'_>> {
+        Ok(PdfPathsCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfPathsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    paths: Vec<Path>,
+    phantom: PhantomData<&'vtab PdfPathsTable>,
+}
+impl PdfPathsCursor<'_> {
+    fn new<'vtab>() -> PdfPathsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfPathsCursor {
+            base,
+            rowid: 0,
+            paths: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfPathsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let page = value_page(&values[0])?;
+        let objects = unsafe { (*page).1.objects() };
+        let mut paths = vec![];
+        for (object_index, object) in objects.iter().enumerate() {
+            let PdfPageObject::Path(path) = &object else {
+                continue;
+            };
+            let stroked = path.is_stroked().unwrap_or(false);
+            let filled = !matches!(path.fill_mode(), Ok(PdfPathFillMode::None) | Err(_));
+            paths.push(Path {
+                object_index,
+                bounds: path.bounds().ok(),
+                segments: segments_json(path)?,
+                stroke_width: path
+                    .stroke_width()
+                    .ok()
+                    .filter(|_| stroked)
+                    .map(|width| width.value),
+                stroke_color: path
+                    .stroke_color()
+                    .ok()
+                    .filter(|_| stroked)
+                    .map(|color| color_hex(&color)),
+                fill_color: path
+                    .fill_color()
+                    .ok()
+                    .filter(|_| filled)
+                    .map(|color| color_hex(&color)),
+                stroked,
+                filled,
+            });
+        }
+        self.paths = paths;
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.paths.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let path = &self.paths[self.rowid];
+        let result_optional = |value: Option<f32>| match value {
+            Some(value) => api::result_double(context, value.into()),
+            None => api::result_null(context),
+        };
+        let result_text = |value: &Option<String>| match value {
+            Some(value) => api::result_text(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        match column(i) {
+            Some(Columns::ObjectIndex) => api::result_int64(context, path.object_index as i64),
+            Some(Columns::X) => result_optional(path.bounds.as_ref().map(|b| b.left.value)),
+            Some(Columns::Y) => result_optional(path.bounds.as_ref().map(|b| b.top.value)),
+            Some(Columns::Width) => result_optional(path.bounds.as_ref().map(|b| b.width().value)),
+            Some(Columns::Height) => {
+                result_optional(path.bounds.as_ref().map(|b| b.height().value))
+            }
+            Some(Columns::Segments) => api::result_json(context, path.segments.clone())?,
+            Some(Columns::StrokeWidth) => result_optional(path.stroke_width),
+            Some(Columns::StrokeColor) => result_text(&path.stroke_color)?,
+            Some(Columns::FillColor) => result_text(&path.fill_color)?,
+            Some(Columns::Stroked) => api::result_bool(context, path.stroked),
+            Some(Columns::Filled) => api::result_bool(context, path.filled),
+            Some(Columns::Page) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
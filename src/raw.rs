@@ -0,0 +1,1143 @@
+use flate2::read::ZlibDecoder;
+use pdfium_render::{document::PdfDocument, pages::PdfPageIndex};
+use sqlite_loadable::{Error, Result};
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::Read,
+    os::raw::{c_int, c_ulong, c_void},
+    slice,
+};
+
+use crate::{merge::import_pages, pdfium, pdfium_error};
+
+// Reading objects straight out of the bytes of a PDF, for the parts of a document that
+// pdfium doesn't expose. This only sees objects written out in the file, not ones that
+// are compressed inside object streams, so documents are read as pdfium saves them.
+
+/// How deeply arrays and dictionaries can be nested before an object is treated as
+/// malformed, the same limit pdfium's own parser has.
+pub(crate) const MAX_DEPTH: usize = 64;
+
+pub(crate) fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+pub(crate) fn rfind(haystack: &[u8], needle: &[u8], before: usize) -> Option<usize> {
+    haystack[..before.min(haystack.len())]
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+pub(crate) fn skip_whitespace(src: &[u8], mut i: usize) -> usize {
+    loop {
+        match src.get(i) {
+            Some(c) if c.is_ascii_whitespace() || *c == 0 => i += 1,
+            // comments run to the end of the line
+            Some(b'%') => {
+                while !matches!(src.get(i), None | Some(b'\r' | b'\n')) {
+                    i += 1;
+                }
+            }
+            _ => return i,
+        }
+    }
+}
+
+fn is_delimiter(c: u8) -> bool {
+    c.is_ascii_whitespace() || b"()<>[]{}/%".contains(&c) || c == 0
+}
+
+/// Reads an unsigned integer at `i`, returning it and the index just past it.
+fn read_number(src: &[u8], i: usize) -> Option<(u32, usize)> {
+    let len = src
+        .get(i..)?
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let number = std::str::from_utf8(&src[i..i + len]).ok()?.parse().ok()?;
+    Some((number, i + len))
+}
+
+/// Reads the `<number> <generation>` in front of the `obj` keyword at `obj`.
+fn object_header(src: &[u8], obj: usize) -> Option<((u32, u32), usize)> {
+    // walk back over `<number> <generation> ` before the `obj` keyword
+    let mut start = obj;
+    for _ in 0..2 {
+        while start > 0 && src[start - 1].is_ascii_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && src[start - 1].is_ascii_digit() {
+            start -= 1;
+        }
+    }
+    let (number, next) = read_number(src, start)?;
+    let (generation, _) = read_number(src, skip_whitespace(src, next))?;
+    Some(((number, generation), obj + b"obj".len()))
+}
+
+fn write_name(name: &[u8], out: &mut Vec<u8>) {
+    out.push(b'/');
+    for &c in name {
+        if c.is_ascii_graphic() && !is_delimiter(c) && c != b'#' {
+            out.push(c);
+        } else {
+            out.extend_from_slice(format!("#{c:02X}").as_bytes());
+        }
+    }
+}
+
+/// A PDF object, as parsed from the raw bytes.
+#[derive(Clone)]
+pub(crate) enum Object {
+    Boolean(bool),
+    Null,
+    Number(f64),
+    String(Vec<u8>),
+    Name(Vec<u8>),
+    Array(Vec<Object>),
+    Dictionary(Vec<(Vec<u8>, Object)>),
+    Reference(u32, u32),
+}
+
+impl Object {
+    /// The value of `key` in a dictionary, without its leading slash.
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&Object> {
+        match self {
+            Object::Dictionary(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_slice() == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Sets `key` in a dictionary, replacing its current value.
+    pub(crate) fn set(&mut self, key: &[u8], value: Object) {
+        if let Object::Dictionary(entries) = self {
+            match entries.iter_mut().find(|(k, _)| k.as_slice() == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key.to_vec(), value)),
+            }
+        }
+    }
+
+    /// Removes `key` from a dictionary.
+    pub(crate) fn remove(&mut self, key: &[u8]) {
+        if let Object::Dictionary(entries) = self {
+            entries.retain(|(k, _)| k.as_slice() != key);
+        }
+    }
+
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            Object::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_name(&self) -> Option<&[u8]> {
+        match self {
+            Object::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Object]> {
+        match self {
+            Object::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_reference(&self) -> Option<(u32, u32)> {
+        match self {
+            Object::Reference(number, generation) => Some((*number, *generation)),
+            _ => None,
+        }
+    }
+
+    /// Decodes a PDF text string, which is either UTF-16BE with a byte order mark, UTF-8
+    /// with a byte order mark (PDF 2.0), or PDFDocEncoding, approximated here as Latin-1.
+    pub(crate) fn as_text(&self) -> Option<String> {
+        let bytes = match self {
+            Object::String(bytes) => bytes,
+            _ => return None,
+        };
+        if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            return Some(String::from_utf16_lossy(&units));
+        }
+        if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+            return Some(String::from_utf8_lossy(utf8).into_owned());
+        }
+        Some(bytes.iter().map(|c| *c as char).collect())
+    }
+
+    /// Writes the object out in PDF syntax. Strings are written as hex strings, which
+    /// don't need any escaping.
+    pub(crate) fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Object::Boolean(value) => {
+                out.extend_from_slice(if *value { b"true" } else { b"false" })
+            }
+            Object::Null => out.extend_from_slice(b"null"),
+            Object::Number(number) => out.extend_from_slice(number.to_string().as_bytes()),
+            Object::String(bytes) => {
+                out.push(b'<');
+                for byte in bytes {
+                    out.extend_from_slice(format!("{byte:02X}").as_bytes());
+                }
+                out.push(b'>');
+            }
+            Object::Name(name) => write_name(name, out),
+            Object::Array(items) => {
+                out.push(b'[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b' ');
+                    }
+                    item.write(out);
+                }
+                out.push(b']');
+            }
+            Object::Dictionary(entries) => {
+                out.extend_from_slice(b"<<");
+                for (key, value) in entries {
+                    write_name(key, out);
+                    out.push(b' ');
+                    value.write(out);
+                }
+                out.extend_from_slice(b">>");
+            }
+            Object::Reference(number, generation) => {
+                out.extend_from_slice(format!("{number} {generation} R").as_bytes())
+            }
+        }
+    }
+}
+
+/// Parses a literal `(string)`, starting just past the opening parenthesis.
+fn parse_literal_string(src: &[u8], mut i: usize) -> Option<(Vec<u8>, usize)> {
+    let mut result = vec![];
+    let mut depth = 0;
+    loop {
+        let c = *src.get(i)?;
+        i += 1;
+        match c {
+            b'(' => {
+                depth += 1;
+                result.push(c);
+            }
+            b')' if depth == 0 => return Some((result, i)),
+            b')' => {
+                depth -= 1;
+                result.push(c);
+            }
+            b'\\' => {
+                let escaped = *src.get(i)?;
+                i += 1;
+                match escaped {
+                    b'n' => result.push(b'\n'),
+                    b'r' => result.push(b'\r'),
+                    b't' => result.push(b'\t'),
+                    b'b' => result.push(0x08),
+                    b'f' => result.push(0x0C),
+                    b'0'..=b'7' => {
+                        let mut value = (escaped - b'0') as u32;
+                        for _ in 0..2 {
+                            match src.get(i) {
+                                Some(digit @ b'0'..=b'7') => {
+                                    value = value * 8 + (digit - b'0') as u32;
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        result.push(value as u8);
+                    }
+                    // a backslash at the end of a line continues the string on the next one
+                    b'\r' => {
+                        if src.get(i) == Some(&b'\n') {
+                            i += 1;
+                        }
+                    }
+                    b'\n' => (),
+                    other => result.push(other),
+                }
+            }
+            c => result.push(c),
+        }
+    }
+}
+
+/// Parses a `<hex string>`, starting just past the opening angle bracket.
+fn parse_hex_string(src: &[u8], i: usize) -> Option<(Vec<u8>, usize)> {
+    let end = find(src, b">", i)?;
+    let digits: Vec<u8> = src[i..end]
+        .iter()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| (*c as char).to_digit(16).unwrap() as u8)
+        .collect();
+    // an odd number of digits is padded with a trailing 0
+    let bytes = digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect();
+    Some((bytes, end + 1))
+}
+
+/// Parses a `/Name`, starting just past the slash.
+fn parse_name(src: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut name = vec![];
+    while let Some(&c) = src.get(i) {
+        if is_delimiter(c) {
+            break;
+        }
+        // `#2F` style escapes
+        let escaped = (c == b'#')
+            .then(|| src.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                name.push(byte);
+                i += 3;
+            }
+            None => {
+                name.push(c);
+                i += 1;
+            }
+        }
+    }
+    (name, i)
+}
+
+/// Parses the object at `i`, returning it and the index just past it.
+pub(crate) fn parse_object(src: &[u8], i: usize) -> Option<(Object, usize)> {
+    parse_nested_object(src, i, 0)
+}
+
+fn parse_nested_object(src: &[u8], i: usize, depth: usize) -> Option<(Object, usize)> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let i = skip_whitespace(src, i);
+    match *src.get(i)? {
+        b'<' if src.get(i + 1) == Some(&b'<') => {
+            let mut entries = vec![];
+            let mut i = i + 2;
+            loop {
+                i = skip_whitespace(src, i);
+                match *src.get(i)? {
+                    b'>' if src.get(i + 1) == Some(&b'>') => {
+                        return Some((Object::Dictionary(entries), i + 2))
+                    }
+                    b'/' => {
+                        let (key, next) = parse_name(src, i + 1);
+                        let (value, next) = parse_nested_object(src, next, depth + 1)?;
+                        entries.push((key, value));
+                        i = next;
+                    }
+                    _ => return None,
+                }
+            }
+        }
+        b'<' => {
+            let (bytes, next) = parse_hex_string(src, i + 1)?;
+            Some((Object::String(bytes), next))
+        }
+        b'(' => {
+            let (bytes, next) = parse_literal_string(src, i + 1)?;
+            Some((Object::String(bytes), next))
+        }
+        b'/' => {
+            let (name, next) = parse_name(src, i + 1);
+            Some((Object::Name(name), next))
+        }
+        b'[' => {
+            let mut items = vec![];
+            let mut i = i + 1;
+            loop {
+                i = skip_whitespace(src, i);
+                if *src.get(i)? == b']' {
+                    return Some((Object::Array(items), i + 1));
+                }
+                let (item, next) = parse_nested_object(src, i, depth + 1)?;
+                items.push(item);
+                i = next;
+            }
+        }
+        b't' if src[i..].starts_with(b"true") => Some((Object::Boolean(true), i + 4)),
+        b'f' if src[i..].starts_with(b"false") => Some((Object::Boolean(false), i + 5)),
+        b'n' if src[i..].starts_with(b"null") => Some((Object::Null, i + 4)),
+        b'0'..=b'9' | b'+' | b'-' | b'.' => {
+            let len = src[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit() || b"+-.".contains(c))
+                .count();
+            let number: f64 = std::str::from_utf8(&src[i..i + len]).ok()?.parse().ok()?;
+            let next = i + len;
+            // `<number> <generation> R` is a reference
+            let reference = read_number(src, i)
+                .filter(|(_, end)| *end == next)
+                .and_then(|(number, _)| {
+                    let (generation, end) = read_number(src, skip_whitespace(src, next))?;
+                    let r = skip_whitespace(src, end);
+                    let is_r = src.get(r) == Some(&b'R')
+                        && src.get(r + 1).copied().is_none_or(is_delimiter);
+                    is_r.then_some((Object::Reference(number, generation), r + 1))
+                });
+            Some(reference.unwrap_or((Object::Number(number), next)))
+        }
+        _ => None,
+    }
+}
+
+/// An indirect object, and the raw data that follows it if it's a stream.
+pub(crate) struct IndirectObject<'a> {
+    pub(crate) value: Object,
+    pub(crate) stream: Option<&'a [u8]>,
+}
+
+impl IndirectObject<'_> {
+    /// The decoded data of a stream that is either unfiltered or uses `FlateDecode`,
+    /// the filter almost every PDF writer uses for embedded data.
+    pub(crate) fn decoded_stream(&self) -> Option<Vec<u8>> {
+        let data = self.stream?;
+        let filter = match self.value.get(b"Filter") {
+            Some(Object::Array(filters)) if filters.len() == 1 => filters[0].as_name(),
+            Some(Object::Array(filters)) if filters.is_empty() => None,
+            Some(Object::Array(_)) => return None,
+            Some(filter) => Some(filter.as_name()?),
+            None => None,
+        };
+        match filter {
+            None => Some(data.to_vec()),
+            Some(b"FlateDecode") => {
+                let mut decoded = vec![];
+                ZlibDecoder::new(data).read_to_end(&mut decoded).ok()?;
+                Some(decoded)
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// Reads the indirect object that starts at `start`, just past its `obj` keyword.
+pub(crate) fn read_object(src: &[u8], start: usize) -> Option<IndirectObject<'_>> {
+    let (value, next) = parse_object(src, start)?;
+    let keyword = skip_whitespace(src, next);
+    if !src[keyword..].starts_with(b"stream") {
+        return Some(IndirectObject {
+            value,
+            stream: None,
+        });
+    }
+    let mut data_start = keyword + b"stream".len();
+    if src.get(data_start) == Some(&b'\r') {
+        data_start += 1;
+    }
+    if src.get(data_start) == Some(&b'\n') {
+        data_start += 1;
+    }
+    // trust a direct /Length if `endstream` is right after it
+    let length = value
+        .get(b"Length")
+        .and_then(Object::as_number)
+        .and_then(|length| data_start.checked_add(length as usize))
+        .filter(|end| {
+            let keyword = skip_whitespace(src, *end);
+            src.get(keyword..)
+                .is_some_and(|rest| rest.starts_with(b"endstream"))
+        });
+    let data_end = match length {
+        Some(end) => end,
+        None => {
+            let mut end = find(src, b"endstream", data_start)?;
+            // drop the end-of-line marker before `endstream`
+            if end > data_start && src[end - 1] == b'\n' {
+                end -= 1;
+            }
+            if end > data_start && src[end - 1] == b'\r' {
+                end -= 1;
+            }
+            end
+        }
+    };
+    Some(IndirectObject {
+        value,
+        stream: src.get(data_start..data_end),
+    })
+}
+
+/// An `FPDF_FILEWRITE` that collects what pdfium saves into memory.
+#[repr(C)]
+struct BytesWriter {
+    version: c_int,
+    write_block: unsafe extern "C" fn(*mut BytesWriter, *const c_void, c_ulong) -> c_int,
+    bytes: Vec<u8>,
+}
+
+unsafe extern "C" fn write_block(
+    writer: *mut BytesWriter,
+    data: *const c_void,
+    size: c_ulong,
+) -> c_int {
+    (*writer)
+        .bytes
+        .extend_from_slice(slice::from_raw_parts(data as *const u8, size as usize));
+    1
+}
+
+/// `FPDF_REMOVE_SECURITY` from fpdf_save.h
+const FPDF_REMOVE_SECURITY: c_ulong = 3;
+
+/// Saves a loaded document for reading with the raw parser. pdfium writes objects out of
+/// compressed object streams when saving, and without its encryption the strings and
+/// streams of a password-protected PDF come out readable.
+pub(crate) fn document_bytes(document: &PdfDocument) -> Result<Vec<u8>> {
+    let bindings = document.bindings();
+    let mut writer = BytesWriter {
+        version: 1,
+        write_block,
+        bytes: vec![],
+    };
+    let saved = bindings.FPDF_SaveAsCopy(
+        bindings.get_handle_from_document(document),
+        &mut writer as *mut BytesWriter as *mut _,
+        FPDF_REMOVE_SECURITY,
+    );
+    if !bindings.is_true(saved) {
+        return Err(Error::new_message("PDF could not be saved for reading"));
+    }
+    Ok(writer.bytes)
+}
+
+/// Reads the index just past the `obj` keyword of each object from the classic
+/// cross-reference tables of a PDF, starting at the last `startxref` and following each
+/// section's `/Prev`. `None` when the file has no usable table, like when it uses
+/// cross-reference streams or its offsets are off.
+fn read_xref(src: &[u8]) -> Option<HashMap<(u32, u32), usize>> {
+    let startxref = rfind(src, b"startxref", src.len())?;
+    let (mut offset, _) = read_number(src, skip_whitespace(src, startxref + b"startxref".len()))?;
+    let mut objects = HashMap::new();
+    // object numbers that a newer section already defined or freed
+    let mut seen = HashSet::new();
+    let mut sections = HashSet::new();
+    // a /Prev that points back at a section already read would loop forever
+    while sections.insert(offset) {
+        let mut i = skip_whitespace(src, offset as usize);
+        if !src.get(i..)?.starts_with(b"xref") {
+            return None;
+        }
+        i += b"xref".len();
+        loop {
+            i = skip_whitespace(src, i);
+            if src[i..].starts_with(b"trailer") {
+                break;
+            }
+            let (first, next) = read_number(src, i)?;
+            let (count, next) = read_number(src, skip_whitespace(src, next))?;
+            i = next;
+            for number in first..first.checked_add(count)? {
+                let (entry_offset, next) = read_number(src, skip_whitespace(src, i))?;
+                let (generation, next) = read_number(src, skip_whitespace(src, next))?;
+                i = skip_whitespace(src, next);
+                let in_use = match src.get(i)? {
+                    b'n' => true,
+                    b'f' => false,
+                    _ => return None,
+                };
+                i += 1;
+                if !seen.insert(number) || !in_use {
+                    continue;
+                }
+                let (id, start) = object_at(src, entry_offset as usize)?;
+                if id != (number, generation) {
+                    return None;
+                }
+                objects.insert(id, start);
+            }
+        }
+        let (trailer, _) = parse_object(src, i + b"trailer".len())?;
+        match trailer.get(b"Prev").and_then(Object::as_number) {
+            Some(prev) if prev >= 0.0 => offset = prev as u32,
+            _ => break,
+        }
+    }
+    Some(objects)
+}
+
+/// Reads the `<number> <generation> obj` header at `i`, returning the object's id and the
+/// index just past the `obj` keyword.
+fn object_at(src: &[u8], i: usize) -> Option<((u32, u32), usize)> {
+    let (number, next) = read_number(src, skip_whitespace(src, i))?;
+    let (generation, next) = read_number(src, skip_whitespace(src, next))?;
+    let obj = skip_whitespace(src, next);
+    let end = obj + b"obj".len();
+    (src.get(obj..end)? == b"obj" && src.get(end).copied().is_none_or(is_delimiter))
+        .then_some(((number, generation), end))
+}
+
+/// Finds the objects of a PDF without a usable cross-reference table by scanning for
+/// `<number> <generation> obj` headers, skipping over stream data so that the objects of
+/// an embedded PDF aren't taken for the document's own.
+fn scan_objects(src: &[u8]) -> HashMap<(u32, u32), usize> {
+    let mut objects = HashMap::new();
+    let mut from = 0;
+    while let Some(obj) = find(src, b"obj", from) {
+        from = obj + b"obj".len();
+        // `endobj` and words that only end in "obj" aren't object headers
+        let is_keyword = obj > 0
+            && src[obj - 1].is_ascii_whitespace()
+            && src.get(from).copied().is_none_or(is_delimiter);
+        let Some((id, start)) = is_keyword.then(|| object_header(src, obj)).flatten() else {
+            continue;
+        };
+        // with incremental updates the last definition in the file is the current one
+        objects.insert(id, start);
+        if let Some(stream) = read_object(src, start).and_then(|object| object.stream) {
+            from = stream.as_ptr() as usize - src.as_ptr() as usize + stream.len();
+        }
+    }
+    objects
+}
+
+/// The raw bytes of a PDF, indexed by where each indirect object is defined.
+pub(crate) struct RawPdf<'a> {
+    src: Cow<'a, [u8]>,
+    /// The index just past each object's `obj` keyword
+    objects: HashMap<(u32, u32), usize>,
+}
+
+impl<'a> RawPdf<'a> {
+    pub(crate) fn new(src: impl Into<Cow<'a, [u8]>>) -> RawPdf<'a> {
+        let src = src.into();
+        let objects = read_xref(&src).unwrap_or_else(|| scan_objects(&src));
+        RawPdf { src, objects }
+    }
+
+    /// Reads a loaded document, as pdfium saves it.
+    pub(crate) fn from_document(document: &PdfDocument) -> Result<RawPdf<'static>> {
+        Ok(RawPdf::new(document_bytes(document)?))
+    }
+
+    /// Reads a single page of a loaded document, as pdfium saves a copy of just that page.
+    /// That's much less to save than the whole document, but the page is at index 0 and its
+    /// objects are renumbered.
+    pub(crate) fn from_page(
+        document: &PdfDocument,
+        page_index: PdfPageIndex,
+    ) -> Result<RawPdf<'static>> {
+        let copy = pdfium().create_new_pdf().map_err(pdfium_error)?;
+        import_pages(&copy, document, &[page_index])?;
+        RawPdf::from_document(&copy)
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.src
+    }
+
+    /// The indirect object with the given id.
+    pub(crate) fn object(&self, id: (u32, u32)) -> Option<IndirectObject<'_>> {
+        read_object(&self.src, *self.objects.get(&id)?)
+    }
+
+    /// Follows `object` if it's a reference, or returns it as is.
+    pub(crate) fn resolve<'o>(&self, object: &'o Object) -> Option<Cow<'o, Object>> {
+        match object.as_reference() {
+            Some(id) => Some(Cow::Owned(self.object(id)?.value)),
+            None => Some(Cow::Borrowed(object)),
+        }
+    }
+
+    /// Every indirect object whose dictionary has a `<key> <name>` entry, like
+    /// `/Type /Filespec`, in the order they're written in the file.
+    pub(crate) fn objects_with<'s>(
+        &'s self,
+        key: &'s [u8],
+        name: &'s [u8],
+    ) -> impl Iterator<Item = ((u32, u32), IndirectObject<'s>)> + 's {
+        let mut objects: Vec<_> = self
+            .objects
+            .iter()
+            .map(|(id, start)| (*id, *start))
+            .collect();
+        objects.sort_unstable_by_key(|(_, start)| *start);
+        objects.into_iter().filter_map(move |(id, start)| {
+            let object = read_object(&self.src, start)?;
+            (object.value.get(key).and_then(Object::as_name) == Some(name)).then_some((id, object))
+        })
+    }
+
+    /// The dictionary of the last trailer, or of the last cross-reference stream, which
+    /// has the trailer's entries.
+    pub(crate) fn trailer(&self) -> Option<Object> {
+        let src = &self.src;
+        let trailer = rfind(src, b"trailer", src.len())
+            .and_then(|i| Some(parse_object(src, i + b"trailer".len())?.0));
+        trailer.or_else(|| Some(self.objects_with(b"Type", b"XRef").last()?.1.value))
+    }
+
+    /// The document catalog.
+    pub(crate) fn catalog(&self) -> Option<Object> {
+        match self
+            .trailer()
+            .and_then(|trailer| trailer.get(b"Root").cloned())
+        {
+            Some(root) => Some(self.resolve(&root)?.into_owned()),
+            // with incremental updates the last catalog in the file is the current one
+            None => Some(self.objects_with(b"Type", b"Catalog").last()?.1.value),
+        }
+    }
+
+    /// The dictionary of the page at `index`, found by walking the page tree from the
+    /// document catalog. Inherited `/Resources` are copied into the page's own dictionary.
+    pub(crate) fn page_dictionary(&self, index: usize) -> Option<Object> {
+        let root = self.resolve(self.catalog()?.get(b"Pages")?)?.into_owned();
+        let mut remaining = index;
+        self.find_page(root, None, &mut remaining, 0)
+    }
+
+    fn find_page(
+        &self,
+        node: Object,
+        resources: Option<&Object>,
+        remaining: &mut usize,
+        depth: usize,
+    ) -> Option<Object> {
+        // guard against page trees that loop back on themselves
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let resources = node.get(b"Resources").or(resources).cloned();
+        let Some(kids) = node.get(b"Kids").and_then(Object::as_array) else {
+            if *remaining > 0 {
+                *remaining -= 1;
+                return None;
+            }
+            let Object::Dictionary(mut entries) = node else {
+                return None;
+            };
+            if let Some(resources) = resources {
+                if !entries.iter().any(|(key, _)| key == b"Resources") {
+                    entries.push((b"Resources".to_vec(), resources));
+                }
+            }
+            return Some(Object::Dictionary(entries));
+        };
+        for kid in kids {
+            let kid = self.resolve(kid)?.into_owned();
+            // skip over whole subtrees that come before the page
+            if let Some(count) = kid.get(b"Count").and_then(Object::as_number) {
+                if kid.get(b"Kids").is_some() && (count as usize) <= *remaining {
+                    *remaining -= count as usize;
+                    continue;
+                }
+            }
+            if let Some(page) = self.find_page(kid, resources.as_ref(), remaining, depth + 1) {
+                return Some(page);
+            }
+        }
+        None
+    }
+
+    /// The decoded content streams of a page, joined together.
+    pub(crate) fn page_contents(&self, page: &Object) -> Option<Vec<u8>> {
+        let streams = match page.get(b"Contents")? {
+            Object::Array(streams) => streams.clone(),
+            contents => vec![contents.clone()],
+        };
+        let mut data = vec![];
+        for stream in streams {
+            data.extend(self.object(stream.as_reference()?)?.decoded_stream()?);
+            // the streams are split between tokens, not inside them
+            data.push(b'\n');
+        }
+        Some(data)
+    }
+
+    /// The PDF with an incremental update appended that replaces the given objects.
+    pub(crate) fn updated(&self, objects: &[((u32, u32), Object)]) -> Option<Vec<u8>> {
+        let trailer = self.trailer()?;
+        let src = self.bytes();
+        let startxref = rfind(src, b"startxref", src.len())?;
+        let previous_xref = parse_object(src, startxref + b"startxref".len())?.0;
+        let mut pdf = src.to_vec();
+        if !pdf.ends_with(b"\n") {
+            pdf.push(b'\n');
+        }
+        let mut offsets = vec![];
+        for ((number, generation), object) in objects {
+            offsets.push(((*number, *generation), pdf.len()));
+            pdf.extend_from_slice(format!("{number} {generation} obj\n").as_bytes());
+            object.write(&mut pdf);
+            pdf.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(b"xref\n");
+        offsets.sort_unstable();
+        for ((number, generation), offset) in offsets {
+            // every entry is exactly 20 bytes long
+            pdf.extend_from_slice(
+                format!("{number} 1\n{offset:010} {generation:05} n \n").as_bytes(),
+            );
+        }
+        let mut new_trailer: Vec<(Vec<u8>, Object)> = [&b"Size"[..], b"Root", b"Info", b"ID"]
+            .iter()
+            .filter_map(|key| Some((key.to_vec(), trailer.get(key)?.clone())))
+            .collect();
+        new_trailer.push((b"Prev".to_vec(), previous_xref));
+        pdf.extend_from_slice(b"trailer\n");
+        Object::Dictionary(new_trailer).write(&mut pdf);
+        pdf.extend_from_slice(format!("\nstartxref\n{xref}\n%%EOF\n").as_bytes());
+        Some(pdf)
+    }
+}
+
+/// Splits a content stream into its operators and their operands, like
+/// `(b"Tf", [/F1, 12])`. The data of inline images is skipped.
+pub(crate) fn content_operations(data: &[u8]) -> Vec<(Vec<u8>, Vec<Object>)> {
+    let mut operations = vec![];
+    let mut operands = vec![];
+    let mut i = 0;
+    loop {
+        i = skip_whitespace(data, i);
+        let Some(&c) = data.get(i) else {
+            break;
+        };
+        if !(c.is_ascii_alphabetic() || c == b'\'' || c == b'"') {
+            match parse_object(data, i) {
+                Some((operand, next)) => {
+                    operands.push(operand);
+                    i = next;
+                }
+                // stray delimiters
+                None => i += 1,
+            }
+            continue;
+        }
+        let len = data[i..].iter().take_while(|c| !is_delimiter(**c)).count();
+        let operator = data[i..i + len].to_vec();
+        i += len;
+        match operator.as_slice() {
+            b"true" | b"false" => {
+                operands.push(Object::Boolean(operator == b"true"));
+                continue;
+            }
+            b"null" => {
+                operands.push(Object::Null);
+                continue;
+            }
+            // inline image data is binary, and ends at an `EI` on its own
+            b"ID" => {
+                let mut end = i + 1;
+                while let Some(ei) = find(data, b"EI", end) {
+                    end = ei + 2;
+                    if data[ei - 1].is_ascii_whitespace()
+                        && data.get(ei + 2).copied().is_none_or(is_delimiter)
+                    {
+                        break;
+                    }
+                }
+                i = end.min(data.len());
+            }
+            _ => (),
+        }
+        operations.push((operator, std::mem::take(&mut operands)));
+    }
+    operations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Option<Object> {
+        Some(parse_object(src.as_bytes(), 0)?.0)
+    }
+
+    #[test]
+    fn parses_dictionaries() {
+        let object =
+            parse("<< /Type /Page /Rotate -90 /Scale 1.5 /Parent 3 0 R /Kids [1 0 R 2] >>")
+                .unwrap();
+        assert_eq!(
+            object.get(b"Type").and_then(Object::as_name),
+            Some(&b"Page"[..])
+        );
+        assert_eq!(
+            object.get(b"Rotate").and_then(Object::as_number),
+            Some(-90.0)
+        );
+        assert_eq!(object.get(b"Scale").and_then(Object::as_number), Some(1.5));
+        assert_eq!(
+            object.get(b"Parent").and_then(Object::as_reference),
+            Some((3, 0))
+        );
+        let kids = object.get(b"Kids").and_then(Object::as_array).unwrap();
+        assert_eq!(kids[0].as_reference(), Some((1, 0)));
+        assert_eq!(kids[1].as_number(), Some(2.0));
+    }
+
+    #[test]
+    fn parses_strings_and_names() {
+        let text = |src: &str| parse(src).and_then(|object| object.as_text());
+        assert_eq!(
+            text(r"(a (nested) \(string\)\n)").unwrap(),
+            "a (nested) (string)\n"
+        );
+        assert_eq!(text(r"(\101\102C)").unwrap(), "ABC");
+        assert_eq!(text("<48656C6C6F>").unwrap(), "Hello");
+        // an odd number of digits is padded with a 0
+        assert_eq!(text("<414>").unwrap(), "A@");
+        assert_eq!(text("<FEFF00E9>").unwrap(), "\u{e9}");
+        assert_eq!(parse("/A#20B").unwrap().as_name(), Some(&b"A B"[..]));
+    }
+
+    #[test]
+    fn writes_objects() {
+        let src = "<< /Type /Page /A#20B [1 -2.5 true false null] /S (a\\)b) /R 12 0 R >>";
+        let mut written = vec![];
+        parse(src).unwrap().write(&mut written);
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "<</Type /Page/A#20B [1 -2.5 true false null]/S <612962>/R 12 0 R>>"
+        );
+    }
+
+    #[test]
+    fn sets_dictionary_entries() {
+        let mut dictionary = parse("<< /V (a) /AP 4 0 R >>").unwrap();
+        dictionary.set(b"V", Object::Name(b"Yes".to_vec()));
+        dictionary.set(b"AS", Object::Name(b"Yes".to_vec()));
+        dictionary.remove(b"AP");
+        let mut written = vec![];
+        dictionary.write(&mut written);
+        assert_eq!(String::from_utf8(written).unwrap(), "<</V /Yes/AS /Yes>>");
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let arrays = "[".repeat(100_000);
+        assert!(parse(&arrays).is_none());
+        let dictionaries = "<< /A ".repeat(100_000);
+        assert!(parse(&dictionaries).is_none());
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&nested).is_some());
+    }
+
+    #[test]
+    fn reads_streams() {
+        let src = b"1 0 obj << /Length 5 >> stream\nhello\nendstream endobj";
+        let (_, start) = object_at(src, 0).unwrap();
+        let object = read_object(src, start).unwrap();
+        assert_eq!(object.stream, Some(&b"hello"[..]));
+        // a /Length past the end of the file falls back to looking for `endstream`
+        let src =
+            b"1 0 obj << /Length 99999999999999999999999 >> stream\r\nhello\r\nendstream endobj";
+        let object = read_object(src, object_at(src, 0).unwrap().1).unwrap();
+        assert_eq!(object.decoded_stream().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn indexes_objects() {
+        let raw = RawPdf::new(
+            &b"%PDF-1.7
+1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj
+3 0 obj << /Type /Page /Note (old) >> endobj
+trailer << /Root 1 0 R >>
+3 0 obj << /Type /Page /Note (new) >> endobj
+trailer << /Root 1 0 R /Prev 9 >>
+"[..],
+        );
+        // with incremental updates the last definition wins
+        let page = raw.object((3, 0)).unwrap().value;
+        assert_eq!(page.get(b"Note").and_then(Object::as_text).unwrap(), "new");
+        assert!(raw.object((4, 0)).is_none());
+        let catalog = raw.catalog().unwrap();
+        assert_eq!(
+            catalog.get(b"Type").and_then(Object::as_name),
+            Some(&b"Catalog"[..])
+        );
+    }
+
+    /// A PDF with a classic cross-reference table for the given objects, numbered from 1.
+    fn with_xref(objects: &[&str]) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        let mut offsets = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj {object} endobj\n", i + 1).as_bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(
+            format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+        );
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer << /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .as_bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn reads_cross_reference_tables() {
+        // an uncompressed PDF embedded in a stream has objects with the same numbers
+        let embedded = "1 0 obj << /Type /Catalog /Embedded true >> endobj";
+        let src = with_xref(&[
+            "<< /Type /Catalog /Pages 2 0 R >>",
+            "<< /Type /Pages /Kids [] /Count 0 >>",
+            &format!(
+                "<< /Type /EmbeddedFile /Length {} >> stream\n{embedded}\nendstream",
+                embedded.len()
+            ),
+        ]);
+        assert!(read_xref(&src).is_some());
+        let raw = RawPdf::new(&src[..]);
+        let catalog = raw.catalog().unwrap();
+        assert!(catalog.get(b"Embedded").is_none());
+        assert_eq!(raw.objects_with(b"Type", b"Catalog").count(), 1);
+        // updates are read through /Prev, and replace the objects they redefine
+        let catalog = parse("<< /Type /Catalog /Pages 2 0 R /Lang (en) >>").unwrap();
+        let updated = RawPdf::new(raw.updated(&[((1, 0), catalog)]).unwrap());
+        assert!(read_xref(updated.bytes()).is_some());
+        let catalog = updated.catalog().unwrap();
+        assert_eq!(
+            catalog.get(b"Lang").and_then(Object::as_text).unwrap(),
+            "en"
+        );
+        assert!(updated.object((2, 0)).is_some());
+        assert!(updated.object((3, 0)).unwrap().stream.is_some());
+    }
+
+    #[test]
+    fn scans_past_streams() {
+        // without a cross-reference table the objects are found by scanning the file
+        let raw = RawPdf::new(
+            &b"1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+3 0 obj << /Length 51 >> stream
+1 0 obj << /Type /Catalog /Embedded true >> endobj
+endstream endobj
+"[..],
+        );
+        assert!(raw.catalog().unwrap().get(b"Embedded").is_none());
+        assert_eq!(raw.objects_with(b"Type", b"Catalog").count(), 1);
+    }
+
+    #[test]
+    fn appends_updates() {
+        let raw = RawPdf::new(
+            &b"%PDF-1.7
+1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [] /Count 0 >> endobj
+xref
+0 3
+trailer << /Size 3 /Root 1 0 R >>
+startxref
+110
+%%EOF"[..],
+        );
+        let catalog = parse("<< /Type /Catalog /Pages 2 0 R /Lang (en) >>").unwrap();
+        let updated = RawPdf::new(raw.updated(&[((1, 0), catalog)]).unwrap());
+        assert!(updated.bytes().starts_with(raw.bytes()));
+        let catalog = updated.catalog().unwrap();
+        assert_eq!(
+            catalog.get(b"Lang").and_then(Object::as_text).unwrap(),
+            "en"
+        );
+        let trailer = updated.trailer().unwrap();
+        assert_eq!(
+            trailer.get(b"Prev").and_then(Object::as_number),
+            Some(110.0)
+        );
+        assert_eq!(trailer.get(b"Size").and_then(Object::as_number), Some(3.0));
+    }
+
+    #[test]
+    fn walks_page_trees() {
+        let raw = RawPdf::new(
+            &b"1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R 4 0 R] /Count 3 /Resources << /Font 9 0 R >> >> endobj
+3 0 obj << /Type /Pages /Kids [5 0 R 6 0 R] /Count 2 >> endobj
+4 0 obj << /Type /Page /Resources << >> /Contents 7 0 R >> endobj
+5 0 obj << /Type /Page /Name (first) >> endobj
+6 0 obj << /Type /Page /Name (second) >> endobj
+7 0 obj << /Length 8 >> stream
+0 0 m BT
+endstream endobj
+trailer << /Root 1 0 R >>
+"[..],
+        );
+        let name = |index| {
+            raw.page_dictionary(index)?
+                .get(b"Name")
+                .and_then(Object::as_text)
+        };
+        assert_eq!(name(0).unwrap(), "first");
+        assert_eq!(name(1).unwrap(), "second");
+        assert!(raw.page_dictionary(3).is_none());
+        // resources are inherited, unless the page has its own
+        let first = raw.page_dictionary(0).unwrap();
+        let resources = first.get(b"Resources").unwrap();
+        assert_eq!(
+            resources.get(b"Font").and_then(Object::as_reference),
+            Some((9, 0))
+        );
+        let third = raw.page_dictionary(2).unwrap();
+        assert!(third.get(b"Resources").unwrap().get(b"Font").is_none());
+        assert_eq!(raw.page_contents(&third).unwrap(), b"0 0 m BT\n");
+    }
+
+    #[test]
+    fn loops_in_page_trees_end() {
+        let raw = RawPdf::new(
+            &b"1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [2 0 R] /Count 1 >> endobj
+trailer << /Root 1 0 R >>
+"[..],
+        );
+        assert!(raw.page_dictionary(0).is_none());
+    }
+
+    #[test]
+    fn splits_content_streams() {
+        let operations = content_operations(
+            b"q /GS1 gs BT /F1 12 Tf [(Hi) -20 (there)] TJ ET BI /W 1 /H 1 ID \x00EI\xff EI Q",
+        );
+        let operators: Vec<&[u8]> = operations.iter().map(|(op, _)| op.as_slice()).collect();
+        assert_eq!(
+            operators,
+            [
+                &b"q"[..],
+                b"gs",
+                b"BT",
+                b"Tf",
+                b"TJ",
+                b"ET",
+                b"BI",
+                b"ID",
+                b"Q"
+            ]
+        );
+        let (_, font) = &operations[3];
+        assert_eq!(font[0].as_name(), Some(&b"F1"[..]));
+        assert_eq!(font[1].as_number(), Some(12.0));
+    }
+}
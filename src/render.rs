@@ -0,0 +1,296 @@
+use image::{DynamicImage, ImageOutputFormat};
+use pdfium_render::{color::PdfColor, page::PdfPageRenderRotation, render_config::PdfRenderConfig};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{api, Error, Result};
+
+use crate::{image_error, pdfium_error, value_arg, value_page};
+
+/// Image encodings that rendered pages and extracted images can be returned as.
+pub(crate) enum OutputFormat {
+    Png,
+    Jpeg(u8),
+    #[cfg(feature = "webp")]
+    WebP,
+}
+
+impl OutputFormat {
+    /// Parses a format name like `'png'`, `'jpeg'`, `'jpeg:80'` or `'webp'`. WebP is only
+    /// available in builds with the non-default `webp` feature, and is an error otherwise.
+    pub(crate) fn parse(format: &str) -> Result<OutputFormat> {
+        let (name, quality) = match format.split_once(':') {
+            Some((name, quality)) => (name, Some(quality)),
+            None => (format, None),
+        };
+        match (name.to_ascii_lowercase().as_str(), quality) {
+            ("png", None) => Ok(OutputFormat::Png),
+            ("jpeg" | "jpg", None) => Ok(OutputFormat::Jpeg(75)),
+            ("jpeg" | "jpg", Some(quality)) => match quality.parse::<u8>() {
+                Ok(quality) if (1..=100).contains(&quality) => Ok(OutputFormat::Jpeg(quality)),
+                _ => Err(Error::new_message(format!(
+                    "JPEG quality must be between 1 and 100, got '{quality}'"
+                ))),
+            },
+            #[cfg(feature = "webp")]
+            ("webp", None) => Ok(OutputFormat::WebP),
+            #[cfg(not(feature = "webp"))]
+            ("webp", None) => Err(Error::new_message(
+                "WebP output requires sqlite-pdf to be built with the webp feature",
+            )),
+            _ => Err(Error::new_message(format!(
+                "unknown image format '{format}', expected one of 'png', 'jpeg', 'jpeg:<quality>' or 'webp'"
+            ))),
+        }
+    }
+}
+
+/// Encodes the given image into the bytes of the given format.
+pub(crate) fn encode_image(image: DynamicImage, format: &OutputFormat) -> Result<Vec<u8>> {
+    let mut c = std::io::Cursor::new(Vec::new());
+    match format {
+        OutputFormat::Png => image.write_to(&mut c, ImageOutputFormat::Png),
+        // JPEG has no alpha channel, so flatten to RGB first
+        OutputFormat::Jpeg(quality) => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut c, ImageOutputFormat::Jpeg(*quality)),
+        #[cfg(feature = "webp")]
+        OutputFormat::WebP => image.write_to(&mut c, ImageOutputFormat::WebP),
+    }
+    .map_err(image_error)?;
+    Ok(c.into_inner())
+}
+
+/// Parses a `'#RRGGBB'` or `'#AARRGGBB'` color.
+pub(crate) fn parse_color(color: &str) -> Result<PdfColor> {
+    PdfColor::from_hex(color).map_err(|_| {
+        Error::new_message(format!(
+            "invalid color '{color}', expected '#RRGGBB' or '#AARRGGBB'"
+        ))
+    })
+}
+
+/// pdf_page_thumbnail(page [, size [, format [, background]]])
+///
+/// `format` is one of `'png'`, `'jpeg'`, `'jpeg:<quality>'`, or `'webp'` when built with
+/// the `webp` feature.
+pub fn pdf_page_thumbnail(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    if values.is_empty() || values.len() > 4 {
+        return Err(Error::new_message(
+            "pdf_page_thumbnail() takes between 1 and 4 arguments",
+        ));
+    }
+    let xx = value_page(&values[0])?;
+    let size = match value_arg(values, 1) {
+        Some(value) => api::value_int(value),
+        None => 256,
+    };
+    if size <= 0 {
+        return Err(Error::new_message(
+            "thumbnail size must be a positive number of pixels",
+        ));
+    }
+    check_pixels("pdf_page_thumbnail", size.into(), size.into())?;
+    let format = match value_arg(values, 2) {
+        Some(value) => OutputFormat::parse(api::value_text(value)?)?,
+        None => OutputFormat::Png,
+    };
+
+    let mut cfg = PdfRenderConfig::new().thumbnail(size);
+    if let Some(value) = value_arg(values, 3) {
+        cfg = cfg.set_clear_color(parse_color(api::value_text(value)?)?);
+    }
+    let bitmap = unsafe { (*xx).1.render_with_config(&cfg) }.map_err(pdfium_error)?;
+
+    api::result_blob(
+        context,
+        encode_image(bitmap.as_image(), &format)?.as_slice(),
+    );
+    Ok(())
+}
+
+/// The most pixels a page is rendered to, enough for a letter-sized page at 600 dpi. The
+/// bitmap pdfium renders into takes 4 bytes a pixel, so past this a single call could
+/// take the host process down.
+const MAX_PIXELS: f64 = 50_000_000.0;
+
+/// Errors when a `width` × `height` pixel image is larger than `MAX_PIXELS`.
+fn check_pixels(function: &str, width: f64, height: f64) -> Result<()> {
+    if width * height > MAX_PIXELS {
+        return Err(Error::new_message(format!(
+            "{function} would render a {width:.0}x{height:.0} image, larger than the limit of {MAX_PIXELS} pixels"
+        )));
+    }
+    Ok(())
+}
+
+/// The size in pixels that a page of `page_width` × `page_height` points renders to, from
+/// a target width and/or height, or otherwise a dpi. One target keeps the page's aspect
+/// ratio, like pdfium-render does.
+fn rendered_size(
+    (page_width, page_height): (f64, f64),
+    target: (Option<i32>, Option<i32>),
+    dpi: f64,
+) -> (f64, f64) {
+    match target {
+        (Some(width), Some(height)) => (width.into(), height.into()),
+        (Some(width), None) => (width.into(), f64::from(width) * page_height / page_width),
+        (None, Some(height)) => (f64::from(height) * page_width / page_height, height.into()),
+        // PDF user space is 72 points per inch
+        (None, None) => (page_width * dpi / 72.0, page_height * dpi / 72.0),
+    }
+}
+
+/// Reads a boolean option out of a pdf_page_render() options object.
+fn option_bool(key: &str, value: &serde_json::Value) -> Result<bool> {
+    value.as_bool().ok_or_else(|| {
+        Error::new_message(format!("pdf_page_render option '{key}' must be a boolean"))
+    })
+}
+
+/// Reads a pixel size option out of a pdf_page_render() options object.
+fn option_pixels(key: &str, value: &serde_json::Value) -> Result<i32> {
+    match value.as_i64() {
+        Some(pixels) if pixels > 0 && pixels <= i32::MAX.into() => Ok(pixels as i32),
+        _ => Err(Error::new_message(format!(
+            "pdf_page_render option '{key}' must be a positive number of pixels"
+        ))),
+    }
+}
+
+/// Reads a string option out of a pdf_page_render() options object.
+fn option_str<'a>(key: &str, value: &'a serde_json::Value) -> Result<&'a str> {
+    value.as_str().ok_or_else(|| {
+        Error::new_message(format!("pdf_page_render option '{key}' must be a string"))
+    })
+}
+
+/// pdf_page_render(page, dpi [, options])
+///
+/// `options` is a JSON object with any of the keys `width`, `height`, `grayscale`,
+/// `annotations`, `form_fields`, `rotation`, `format` and `background`. A `width` or
+/// `height` overrides `dpi`, which can then be NULL.
+/// `format` takes the same values as `pdf_page_thumbnail`'s.
+pub fn pdf_page_render(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    if values.len() < 2 || values.len() > 3 {
+        return Err(Error::new_message(
+            "pdf_page_render() takes either 2 or 3 arguments",
+        ));
+    }
+    let xx = value_page(&values[0])?;
+    let dpi = value_arg(values, 1).map(api::value_double);
+
+    let mut cfg = PdfRenderConfig::new().use_print_quality(true);
+    let mut format = OutputFormat::Png;
+    let mut target = (None, None);
+    if let Some(value) = value_arg(values, 2) {
+        let options = api::value_json(value).map_err(|err| {
+            Error::new_message(format!(
+                "pdf_page_render options must be a JSON object: {err}"
+            ))
+        })?;
+        let options = options
+            .as_object()
+            .ok_or_else(|| Error::new_message("pdf_page_render options must be a JSON object"))?;
+        for (key, value) in options {
+            cfg = match key.as_str() {
+                "width" => {
+                    let width = option_pixels(key, value)?;
+                    target.0 = Some(width);
+                    cfg.set_target_width(width)
+                }
+                "height" => {
+                    let height = option_pixels(key, value)?;
+                    target.1 = Some(height);
+                    cfg.set_target_height(height)
+                }
+                "grayscale" => cfg.use_grayscale_rendering(option_bool(key, value)?),
+                "annotations" => cfg.render_annotations(option_bool(key, value)?),
+                "form_fields" => cfg.render_form_data(option_bool(key, value)?),
+                "rotation" => {
+                    let rotation = match value.as_i64() {
+                        Some(0) => PdfPageRenderRotation::None,
+                        Some(90) => PdfPageRenderRotation::Degrees90,
+                        Some(180) => PdfPageRenderRotation::Degrees180,
+                        Some(270) => PdfPageRenderRotation::Degrees270,
+                        _ => return Err(Error::new_message(
+                            "pdf_page_render option 'rotation' must be one of 0, 90, 180 or 270",
+                        )),
+                    };
+                    cfg.rotate(rotation, true)
+                }
+                "format" => {
+                    format = OutputFormat::parse(option_str(key, value)?)?;
+                    cfg
+                }
+                "background" => cfg.set_clear_color(parse_color(option_str(key, value)?)?),
+                _ => {
+                    return Err(Error::new_message(format!(
+                        "unknown pdf_page_render option '{key}'"
+                    )))
+                }
+            };
+        }
+    }
+    let dpi = match (target, dpi) {
+        ((None, None), Some(dpi)) if dpi > 0.0 => {
+            cfg = cfg.scale_page_by_factor((dpi / 72.0) as f32);
+            dpi
+        }
+        ((None, None), Some(_)) => {
+            return Err(Error::new_message("pdf_page_render dpi must be positive"))
+        }
+        ((None, None), None) => {
+            return Err(Error::new_message(
+                "pdf_page_render requires a dpi, or a width or height option",
+            ))
+        }
+        // a width or height overrides the dpi
+        _ => 72.0,
+    };
+    let page = unsafe { &(*xx).1 };
+    let (width, height) = rendered_size(
+        (page.width().value.into(), page.height().value.into()),
+        target,
+        dpi,
+    );
+    check_pixels("pdf_page_render", width, height)?;
+
+    let bitmap = page.render_with_config(&cfg).map_err(pdfium_error)?;
+
+    api::result_blob(
+        context,
+        encode_image(bitmap.as_image(), &format)?.as_slice(),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_renders() {
+        let letter = (612.0, 792.0);
+        assert_eq!(rendered_size(letter, (None, None), 144.0), (1224.0, 1584.0));
+        assert_eq!(
+            rendered_size(letter, (Some(306), None), 0.0),
+            (306.0, 396.0)
+        );
+        assert_eq!(
+            rendered_size(letter, (None, Some(396)), 0.0),
+            (306.0, 396.0)
+        );
+        assert_eq!(
+            rendered_size(letter, (Some(10), Some(20)), 0.0),
+            (10.0, 20.0)
+        );
+        // a letter-sized page fits at 600 dpi, but not much beyond
+        let (width, height) = rendered_size(letter, (None, None), 600.0);
+        assert!(check_pixels("f", width, height).is_ok());
+        let (width, height) = rendered_size(letter, (None, None), 1e6);
+        assert!(check_pixels("f", width, height).is_err());
+        let (width, height) = rendered_size(letter, (Some(1_000_000), None), 0.0);
+        assert!(check_pixels("f", width, height).is_err());
+    }
+}
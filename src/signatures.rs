@@ -0,0 +1,247 @@
+use pdfium_render::document::PdfDocument;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{
+    marker::PhantomData,
+    mem,
+    os::raw::{c_char, c_int},
+};
+
+use crate::{load_pdf, metadata::pdf_date_iso8601, pdfium, value_arg};
+
+static CREATE_SQL: &str = "CREATE TABLE x(signing_time text, reason text, sub_filter text, byte_range text, contents blob, docmdp_permission int, pdf hidden, password hidden)";
+enum Columns {
+    SigningTime,
+    Reason,
+    SubFilter,
+    ByteRange,
+    Contents,
+    DocmdpPermission,
+    Pdf,
+    Password,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::SigningTime),
+        1 => Some(Columns::Reason),
+        2 => Some(Columns::SubFilter),
+        3 => Some(Columns::ByteRange),
+        4 => Some(Columns::Contents),
+        5 => Some(Columns::DocmdpPermission),
+        6 => Some(Columns::Pdf),
+        7 => Some(Columns::Password),
+        _ => None,
+    }
+}
+
+struct Signature {
+    signing_time: Option<String>,
+    reason: Option<String>,
+    sub_filter: Option<String>,
+    /// (offset, length) pairs of the signed parts of the file
+    byte_range: Vec<(i32, i32)>,
+    contents: Vec<u8>,
+    /// 1 (no changes), 2 (form filling and signing) or 3 (also annotations)
+    docmdp_permission: Option<u32>,
+}
+
+/// Reads the `/SubFilter`, `/ByteRange` and DocMDP permission of the signature at `index`,
+/// which pdfium-render doesn't expose.
+fn signature_details(
+    document: &PdfDocument,
+    index: u16,
+) -> (Option<String>, Vec<(i32, i32)>, Option<u32>) {
+    let bindings = document.bindings();
+    let handle = bindings
+        .FPDF_GetSignatureObject(bindings.get_handle_from_document(document), index as c_int);
+    if handle.is_null() {
+        return (None, vec![], None);
+    }
+
+    // the length is in bytes of ASCII, including the terminating NUL
+    let len = bindings.FPDFSignatureObj_GetSubFilter(handle, std::ptr::null_mut(), 0);
+    let sub_filter = (len > 1).then(|| {
+        let mut buffer = vec![0u8; len as usize];
+        bindings.FPDFSignatureObj_GetSubFilter(handle, buffer.as_mut_ptr() as *mut c_char, len);
+        String::from_utf8_lossy(&buffer[..len as usize - 1]).into_owned()
+    });
+
+    let len = bindings.FPDFSignatureObj_GetByteRange(handle, std::ptr::null_mut(), 0);
+    let mut buffer = vec![0 as c_int; len as usize];
+    bindings.FPDFSignatureObj_GetByteRange(handle, buffer.as_mut_ptr(), len);
+    let byte_range = buffer
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect();
+
+    // 0 means the signature has no DocMDP transform
+    let permission = bindings.FPDFSignatureObj_GetDocMDPPermission(handle);
+    (
+        sub_filter,
+        byte_range,
+        (permission > 0).then_some(permission),
+    )
+}
+
+#[repr(C)]
+pub struct PdfSignaturesTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfSignaturesTable {
+    type Aux = ();
+    type Cursor = PdfSignaturesCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfSignaturesTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfSignaturesTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_pdf = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Pdf) => {
+                    has_pdf = true;
+                    1
+                }
+                Some(Columns::Password) => 2,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_pdf {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfSignaturesCursor<'_>> {
+        Ok(PdfSignaturesCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfSignaturesCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    signatures: Vec<Signature>,
+    phantom: PhantomData<&'vtab PdfSignaturesTable>,
+}
+impl PdfSignaturesCursor<'_> {
+    fn new<'vtab>() -> PdfSignaturesCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfSignaturesCursor {
+            base,
+            rowid: 0,
+            signatures: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfSignaturesCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let src = api::value_blob(&values[0]);
+        let password = match value_arg(values, 1) {
+            Some(value) => Some(api::value_text(value)?),
+            None => None,
+        };
+        let document = load_pdf(pdfium(), src, password, "pdf_signatures")?;
+        self.signatures = document
+            .signatures()
+            .iter()
+            .enumerate()
+            .map(|(index, signature)| {
+                let (sub_filter, byte_range, docmdp_permission) =
+                    signature_details(&document, index as u16);
+                Signature {
+                    // dates that don't follow the PDF date format are passed through as-is
+                    signing_time: signature
+                        .signing_date()
+                        .map(|date| pdf_date_iso8601(&date).unwrap_or(date)),
+                    reason: signature.reason(),
+                    sub_filter,
+                    byte_range,
+                    contents: signature.bytes(),
+                    docmdp_permission,
+                }
+            })
+            .collect();
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.signatures.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let signature = &self.signatures[self.rowid];
+        let result_text = |value: &Option<String>| match value {
+            Some(value) => api::result_text(context, value),
+            None => {
+                api::result_null(context);
+                Ok(())
+            }
+        };
+        match column(i) {
+            Some(Columns::SigningTime) => result_text(&signature.signing_time)?,
+            Some(Columns::Reason) => result_text(&signature.reason)?,
+            Some(Columns::SubFilter) => result_text(&signature.sub_filter)?,
+            Some(Columns::ByteRange) => {
+                api::result_json(context, serde_json::json!(signature.byte_range))?
+            }
+            Some(Columns::Contents) => api::result_blob(context, &signature.contents),
+            Some(Columns::DocmdpPermission) => match signature.docmdp_permission {
+                Some(permission) => api::result_int64(context, permission.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Pdf) | Some(Columns::Password) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
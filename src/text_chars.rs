@@ -0,0 +1,190 @@
+use pdfium_render::page_text::PdfPageText;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Error, Result,
+};
+
+use std::{marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{color_hex, pdfium_error, value_page};
+
+static CREATE_SQL: &str = "CREATE TABLE x(char text, unicode int, x, y, width, height, font_name text, font_size, fill_color text, rotation, page hidden)";
+enum Columns {
+    Char,
+    Unicode,
+    X,
+    Y,
+    Width,
+    Height,
+    FontName,
+    FontSize,
+    FillColor,
+    Rotation,
+    Page,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Char),
+        1 => Some(Columns::Unicode),
+        2 => Some(Columns::X),
+        3 => Some(Columns::Y),
+        4 => Some(Columns::Width),
+        5 => Some(Columns::Height),
+        6 => Some(Columns::FontName),
+        7 => Some(Columns::FontSize),
+        8 => Some(Columns::FillColor),
+        9 => Some(Columns::Rotation),
+        10 => Some(Columns::Page),
+        _ => None,
+    }
+}
+
+#[repr(C)]
+pub struct PdfTextCharsTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfTextCharsTable {
+    type Aux = ();
+    type Cursor = PdfTextCharsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfTextCharsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfTextCharsTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_page = false;
+        for mut constraint in info.constraints() {
+            match column(constraint.column_idx()) {
+                Some(Columns::Page) => {
+                    if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                        constraint.set_omit(true);
+                        constraint.set_argv_index(1);
+                        has_page = true;
+                    } else {
+                        return Err(BestIndexError::Constraint);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !has_page {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfTextCharsCursor<'_>> {
+        Ok(PdfTextCharsCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfTextCharsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    len: usize,
+    text: Option<PdfPageText<'vtab>>,
+    phantom: PhantomData<&'vtab PdfTextCharsTable>,
+}
+impl PdfTextCharsCursor<'_> {
+    fn new<'vtab>() -> PdfTextCharsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfTextCharsCursor {
+            base,
+            rowid: 0,
+            len: 0,
+            text: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfTextCharsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let page = value_page(&values[0])?;
+        let text = unsafe { (*page).1.text() }.map_err(pdfium_error)?;
+        self.len = text.chars().len();
+        self.text = Some(text);
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.len
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let text = self
+            .text
+            .as_ref()
+            .ok_or_else(|| Error::new_message("pdf_text_chars cursor has no page text"))?;
+        let chars = text.chars();
+        let c = chars.get(self.rowid).map_err(pdfium_error)?;
+        let bounds = || {
+            c.loose_bounds()
+                .map_err(|_| Error::new_message("character is missing a bounds box"))
+        };
+        match column(i) {
+            Some(Columns::Char) => match c.unicode_string() {
+                Some(s) => api::result_text(context, s)?,
+                None => api::result_null(context),
+            },
+            Some(Columns::Unicode) => api::result_int64(context, c.unicode_value().into()),
+            Some(Columns::X) => api::result_double(context, bounds()?.left.value.into()),
+            Some(Columns::Y) => api::result_double(context, bounds()?.top.value.into()),
+            Some(Columns::Width) => api::result_double(context, bounds()?.width().value.into()),
+            Some(Columns::Height) => api::result_double(context, bounds()?.height().value.into()),
+            Some(Columns::FontName) => api::result_text(context, c.font_name())?,
+            Some(Columns::FontSize) => {
+                api::result_double(context, c.scaled_font_size().value.into())
+            }
+            Some(Columns::FillColor) => match c.fill_color() {
+                Ok(color) => api::result_text(context, color_hex(&color))?,
+                Err(_) => api::result_null(context),
+            },
+            Some(Columns::Rotation) => match c.angle_degrees() {
+                Ok(angle) => api::result_double(context, angle.into()),
+                Err(_) => api::result_null(context),
+            },
+            Some(Columns::Page) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
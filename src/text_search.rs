@@ -0,0 +1,325 @@
+use pdfium_render::{page::PdfPage, rect::PdfRect};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Error, Result,
+};
+
+use std::{
+    marker::PhantomData,
+    mem,
+    os::raw::{c_int, c_ulong},
+};
+
+use crate::{value_arg, value_page};
+
+static CREATE_SQL: &str = "CREATE TABLE x(char_index int, length int, text text, x, y, width, height, rects, page hidden, query hidden, flags hidden)";
+enum Columns {
+    CharIndex,
+    Length,
+    Text,
+    X,
+    Y,
+    Width,
+    Height,
+    Rects,
+    Page,
+    Query,
+    Flags,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::CharIndex),
+        1 => Some(Columns::Length),
+        2 => Some(Columns::Text),
+        3 => Some(Columns::X),
+        4 => Some(Columns::Y),
+        5 => Some(Columns::Width),
+        6 => Some(Columns::Height),
+        7 => Some(Columns::Rects),
+        8 => Some(Columns::Page),
+        9 => Some(Columns::Query),
+        10 => Some(Columns::Flags),
+        _ => None,
+    }
+}
+
+// search flags, from fpdf_text.h
+const FPDF_MATCHCASE: c_ulong = 0x1;
+const FPDF_MATCHWHOLEWORD: c_ulong = 0x2;
+
+/// Parses pdf_text_search flags: `c` for a case-sensitive search, `w` to only match whole words.
+fn search_flags(flags: &str) -> Result<c_ulong> {
+    let mut search_flags = 0;
+    for flag in flags.chars() {
+        search_flags |= match flag {
+            'c' => FPDF_MATCHCASE,
+            'w' => FPDF_MATCHWHOLEWORD,
+            _ => {
+                return Err(Error::new_message(format!(
+                    "unknown pdf_text_search flag '{flag}', expected 'c' (match case) or 'w' (whole word)"
+                )))
+            }
+        };
+    }
+    Ok(search_flags)
+}
+
+/// A single match of a pdf_text_search query.
+struct SearchHit {
+    char_index: usize,
+    length: usize,
+    text: String,
+    rects: Vec<PdfRect>,
+}
+
+/// The box around all of a hit's rectangles, or `None` for a hit that pdfium has no
+/// rectangles for.
+fn hit_bounds(rects: &[PdfRect]) -> Option<PdfRect> {
+    rects.iter().copied().reduce(|bounds, rect| {
+        PdfRect::new_from_values(
+            bounds.bottom.value.min(rect.bottom.value),
+            bounds.left.value.min(rect.left.value),
+            bounds.top.value.max(rect.top.value),
+            bounds.right.value.max(rect.right.value),
+        )
+    })
+}
+
+/// Searches the text of a page. pdfium-render's search doesn't expose the character range
+/// of each result, so this goes through pdfium's own search functions instead. The pdfium
+/// handle types aren't exported by pdfium-render, so this has to stay in a single function
+/// where they can be inferred.
+fn search_hits(page: &PdfPage, query: &str, flags: c_ulong) -> Result<Vec<SearchHit>> {
+    let bindings = page.bindings();
+    let text_page = bindings.FPDFText_LoadPage(bindings.get_handle_from_page(page));
+    if text_page.is_null() {
+        return Err(Error::new_message("page text could not be loaded"));
+    }
+    let search = bindings.FPDFText_FindStart_str(text_page, query, flags, 0);
+    let mut hits = vec![];
+    while !search.is_null() && bindings.is_true(bindings.FPDFText_FindNext(search)) {
+        let char_index = bindings.FPDFText_GetSchResultIndex(search);
+        let length = bindings.FPDFText_GetSchCount(search);
+        if char_index < 0 || length <= 0 {
+            continue;
+        }
+        let rect_count = bindings.FPDFText_CountRects(text_page, char_index, length);
+        let rects = (0..rect_count)
+            .filter_map(|i| {
+                let (mut left, mut top, mut right, mut bottom) = (0.0, 0.0, 0.0, 0.0);
+                let found = bindings.FPDFText_GetRect(
+                    text_page,
+                    i,
+                    &mut left,
+                    &mut top,
+                    &mut right,
+                    &mut bottom,
+                );
+                bindings.is_true(found).then(|| {
+                    PdfRect::new_from_values(bottom as f32, left as f32, top as f32, right as f32)
+                })
+            })
+            .collect();
+        // the text is written with a terminating NUL, which is included in the count
+        let mut buffer = vec![0u16; length as usize + 1];
+        let written = bindings.FPDFText_GetText(text_page, char_index, length, buffer.as_mut_ptr());
+        buffer.truncate(written.saturating_sub(1).max(0) as usize);
+        hits.push(SearchHit {
+            char_index: char_index as usize,
+            length: length as usize,
+            text: String::from_utf16_lossy(&buffer),
+            rects,
+        });
+    }
+    if !search.is_null() {
+        bindings.FPDFText_FindClose(search);
+    }
+    bindings.FPDFText_ClosePage(text_page);
+    Ok(hits)
+}
+
+#[repr(C)]
+pub struct PdfTextSearchTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for PdfTextSearchTable {
+    type Aux = ();
+    type Cursor = PdfTextSearchCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfTextSearchTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfTextSearchTable { base };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_page = false;
+        let mut has_query = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Page) => {
+                    has_page = true;
+                    1
+                }
+                Some(Columns::Query) => {
+                    has_query = true;
+                    2
+                }
+                Some(Columns::Flags) => 3,
+                _ => continue,
+            };
+            if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                constraint.set_omit(true);
+                constraint.set_argv_index(argv_index);
+            } else {
+                return Err(BestIndexError::Constraint);
+            }
+        }
+        if !has_page || !has_query {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfTextSearchCursor<'_>> {
+        Ok(PdfTextSearchCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct PdfTextSearchCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    rowid: usize,
+    hits: Vec<SearchHit>,
+    phantom: PhantomData<&'vtab PdfTextSearchTable>,
+}
+impl PdfTextSearchCursor<'_> {
+    fn new<'vtab>() -> PdfTextSearchCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfTextSearchCursor {
+            base,
+            rowid: 0,
+            hits: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfTextSearchCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let page = value_page(&values[0])?;
+        let query = api::value_text(&values[1])?;
+        let flags = match value_arg(values, 2) {
+            Some(value) => search_flags(api::value_text(value)?)?,
+            None => 0,
+        };
+        self.hits = if query.is_empty() {
+            vec![]
+        } else {
+            search_hits(unsafe { &(*page).1 }, query, flags)?
+        };
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.hits.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let hit = &self.hits[self.rowid];
+        let bounds = hit_bounds(&hit.rects);
+        match column(i) {
+            Some(Columns::CharIndex) => api::result_int64(context, hit.char_index as i64),
+            Some(Columns::Length) => api::result_int64(context, hit.length as i64),
+            Some(Columns::Text) => api::result_text(context, &hit.text)?,
+            Some(Columns::X) => match bounds {
+                Some(bounds) => api::result_double(context, bounds.left.value.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Y) => match bounds {
+                Some(bounds) => api::result_double(context, bounds.top.value.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Width) => match bounds {
+                Some(bounds) => api::result_double(context, bounds.width().value.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Height) => match bounds {
+                Some(bounds) => api::result_double(context, bounds.height().value.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Rects) => {
+                let rects = hit
+                    .rects
+                    .iter()
+                    .map(|rect| {
+                        serde_json::json!({
+                            "x": rect.left.value,
+                            "y": rect.top.value,
+                            "width": rect.width().value,
+                            "height": rect.height().value,
+                        })
+                    })
+                    .collect();
+                api::result_json(context, serde_json::Value::Array(rects))?;
+            }
+            Some(Columns::Page) | Some(Columns::Query) | Some(Columns::Flags) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_hits() {
+        assert!(hit_bounds(&[]).is_none());
+        let bounds = hit_bounds(&[
+            PdfRect::new_from_values(700.0, 72.0, 712.0, 300.0),
+            PdfRect::new_from_values(686.0, 50.0, 698.0, 120.0),
+        ])
+        .unwrap();
+        assert_eq!(bounds.left.value, 50.0);
+        assert_eq!(bounds.top.value, 712.0);
+        assert_eq!(bounds.width().value, 250.0);
+        assert_eq!(bounds.height().value, 26.0);
+    }
+}
@@ -0,0 +1,337 @@
+use pdfium_render::{page_text::PdfPageText, rect::PdfRect};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{ConstraintOperator, IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Result,
+};
+
+use std::{collections::HashMap, marker::PhantomData, mem, os::raw::c_int};
+
+use crate::{font_weight_value, pdfium_error, value_page};
+
+static CREATE_SQL: &str = "CREATE TABLE x(text text, reading_order int, x, y, width, height, font_name text, font_size, font_weight int, page hidden)";
+enum Columns {
+    Text,
+    ReadingOrder,
+    X,
+    Y,
+    Width,
+    Height,
+    FontName,
+    FontSize,
+    FontWeight,
+    Page,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Text),
+        1 => Some(Columns::ReadingOrder),
+        2 => Some(Columns::X),
+        3 => Some(Columns::Y),
+        4 => Some(Columns::Width),
+        5 => Some(Columns::Height),
+        6 => Some(Columns::FontName),
+        7 => Some(Columns::FontSize),
+        8 => Some(Columns::FontWeight),
+        9 => Some(Columns::Page),
+        _ => None,
+    }
+}
+
+/// How the text segments of a page are grouped into rows.
+#[derive(Clone, Copy)]
+pub enum Grouping {
+    /// Whitespace-separated words, as in pdf_text_words
+    Words,
+    /// Segments sharing a baseline, as in pdf_text_lines
+    Lines,
+}
+
+/// A run of text on a page (a word or a line) and its dominant font.
+struct TextRun {
+    text: String,
+    bounds: PdfRect,
+    font_name: String,
+    font_size: f32,
+    font_weight: Option<u32>,
+}
+
+/// Tallies characters by font, to find the font most of a run is set in.
+#[derive(Default)]
+struct FontTally {
+    counts: HashMap<(String, u32, Option<u32>), usize>,
+}
+
+impl FontTally {
+    fn add(&mut self, font_name: String, font_size: f32, font_weight: Option<u32>) {
+        // font sizes are bucketed to 1/100th of a point so they can be hashed
+        let key = (font_name, (font_size * 100.0).round() as u32, font_weight);
+        *self.counts.entry(key).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: FontTally) {
+        for (key, count) in other.counts {
+            *self.counts.entry(key).or_default() += count;
+        }
+    }
+
+    fn dominant(&self) -> (String, f32, Option<u32>) {
+        self.counts
+            .iter()
+            .max_by(|(a_key, a), (b_key, b)| a.cmp(b).then_with(|| b_key.cmp(a_key)))
+            .map(|((name, size, weight), _)| (name.clone(), *size as f32 / 100.0, *weight))
+            .unwrap_or_default()
+    }
+}
+
+/// A run of text that is still being built up.
+#[derive(Default)]
+struct PendingRun {
+    text: String,
+    bounds: Option<PdfRect>,
+    fonts: FontTally,
+}
+
+impl PendingRun {
+    fn extend_bounds(&mut self, rect: PdfRect) {
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => union(&bounds, &rect),
+            None => rect,
+        });
+    }
+
+    fn finish(self) -> Option<TextRun> {
+        let text = self.text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let (font_name, font_size, font_weight) = self.fonts.dominant();
+        Some(TextRun {
+            text: text.to_owned(),
+            bounds: self.bounds?,
+            font_name,
+            font_size,
+            font_weight,
+        })
+    }
+}
+
+fn union(a: &PdfRect, b: &PdfRect) -> PdfRect {
+    PdfRect::new(
+        a.bottom.min(b.bottom),
+        a.left.min(b.left),
+        a.top.max(b.top),
+        a.right.max(b.right),
+    )
+}
+
+/// Whether two rects overlap vertically by at least half of the shorter one's height,
+/// i.e. whether they sit on the same line of text.
+fn same_line(a: &PdfRect, b: &PdfRect) -> bool {
+    let overlap = a.top.min(b.top).value - a.bottom.max(b.bottom).value;
+    let shorter = a.height().value.min(b.height().value);
+    shorter <= 0.0 || overlap >= shorter / 2.0
+}
+
+/// Groups the text of a page into words or lines, using pdfium's text segments.
+fn text_runs(text: &PdfPageText, grouping: Grouping) -> Vec<TextRun> {
+    let mut runs = vec![];
+    let mut line: Option<PendingRun> = None;
+    for segment in text.segments().iter() {
+        let segment_bounds = segment.bounds();
+        // segments without any characters inside them have nothing to contribute
+        let chars = match segment.chars() {
+            Ok(chars) => chars,
+            Err(_) => continue,
+        };
+        let mut word = PendingRun::default();
+        let mut segment_run = PendingRun::default();
+        for c in chars.iter() {
+            let ch = c.unicode_char().unwrap_or(' ');
+            segment_run.text.push(ch);
+            if ch.is_whitespace() {
+                if let Some(run) = mem::take(&mut word).finish() {
+                    runs.push(run);
+                }
+                continue;
+            }
+            let font_size = c.scaled_font_size().value;
+            let font_weight = c.font_weight().map(font_weight_value);
+            segment_run.fonts.add(c.font_name(), font_size, font_weight);
+            if let Grouping::Words = grouping {
+                word.text.push(ch);
+                word.fonts.add(c.font_name(), font_size, font_weight);
+                word.extend_bounds(c.loose_bounds().unwrap_or(segment_bounds));
+            }
+        }
+        match grouping {
+            Grouping::Words => {
+                if let Some(run) = word.finish() {
+                    runs.push(run);
+                }
+            }
+            Grouping::Lines => {
+                segment_run.bounds = Some(segment_bounds);
+                line = match line {
+                    Some(mut pending) if matches!(pending.bounds, Some(bounds) if same_line(&bounds, &segment_bounds)) =>
+                    {
+                        let needs_space = !pending.text.ends_with(char::is_whitespace)
+                            && !segment_run.text.starts_with(char::is_whitespace);
+                        if needs_space {
+                            pending.text.push(' ');
+                        }
+                        pending.text.push_str(&segment_run.text);
+                        pending.extend_bounds(segment_bounds);
+                        pending.fonts.merge(segment_run.fonts);
+                        Some(pending)
+                    }
+                    Some(pending) => {
+                        if let Some(run) = pending.finish() {
+                            runs.push(run);
+                        }
+                        Some(segment_run)
+                    }
+                    None => Some(segment_run),
+                };
+            }
+        }
+    }
+    if let Some(run) = line.and_then(PendingRun::finish) {
+        runs.push(run);
+    }
+    runs
+}
+
+#[repr(C)]
+pub struct PdfTextSegmentsTable {
+    /// must be first
+    base: sqlite3_vtab,
+    grouping: Grouping,
+}
+
+impl<'vtab> VTab<'vtab> for PdfTextSegmentsTable {
+    type Aux = Grouping;
+    type Cursor = PdfTextSegmentsCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, PdfTextSegmentsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = PdfTextSegmentsTable {
+            base,
+            grouping: aux.copied().unwrap_or(Grouping::Lines),
+        };
+        // TODO db.config(VTabConfig::Innocuous)?;
+        Ok((CREATE_SQL.to_owned(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_page = false;
+        for mut constraint in info.constraints() {
+            match column(constraint.column_idx()) {
+                Some(Columns::Page) => {
+                    if constraint.usable() && constraint.op() == Some(ConstraintOperator::EQ) {
+                        constraint.set_omit(true);
+                        constraint.set_argv_index(1);
+                        has_page = true;
+                    } else {
+                        return Err(BestIndexError::Constraint);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !has_page {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100000);
+        info.set_idxnum(2);
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<PdfTextSegmentsCursor<'_>> {
+        Ok(PdfTextSegmentsCursor::new(self.grouping))
+    }
+}
+
+#[repr(C)]
+pub struct PdfTextSegmentsCursor<'vtab> {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    grouping: Grouping,
+    rowid: usize,
+    runs: Vec<TextRun>,
+    phantom: PhantomData<&'vtab PdfTextSegmentsTable>,
+}
+impl PdfTextSegmentsCursor<'_> {
+    fn new<'vtab>(grouping: Grouping) -> PdfTextSegmentsCursor<'vtab> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        PdfTextSegmentsCursor {
+            base,
+            grouping,
+            rowid: 0,
+            runs: vec![],
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl VTabCursor for PdfTextSegmentsCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let page = value_page(&values[0])?;
+        let text = unsafe { (*page).1.text() }.map_err(pdfium_error)?;
+        self.runs = text_runs(&text, self.grouping);
+        self.rowid = 0;
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid >= self.runs.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let run = &self.runs[self.rowid];
+        match column(i) {
+            Some(Columns::Text) => api::result_text(context, &run.text)?,
+            Some(Columns::ReadingOrder) => api::result_int64(context, self.rowid as i64),
+            Some(Columns::X) => api::result_double(context, run.bounds.left.value.into()),
+            Some(Columns::Y) => api::result_double(context, run.bounds.top.value.into()),
+            Some(Columns::Width) => api::result_double(context, run.bounds.width().value.into()),
+            Some(Columns::Height) => api::result_double(context, run.bounds.height().value.into()),
+            Some(Columns::FontName) => api::result_text(context, &run.font_name)?,
+            Some(Columns::FontSize) => api::result_double(context, run.font_size.into()),
+            Some(Columns::FontWeight) => match run.font_weight {
+                Some(weight) => api::result_int64(context, weight.into()),
+                None => api::result_null(context),
+            },
+            Some(Columns::Page) => {
+                api::result_null(context);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid as i64)
+    }
+}
@@ -0,0 +1,420 @@
+use quick_xml::{events::Event, name::ResolveResult, NsReader};
+use serde_json::{Map, Value};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{api, Error, Result};
+
+use crate::{load_pdf, pdfium, raw::RawPdf, value_arg};
+
+/// The namespace of the RDF syntax that XMP is written in.
+const RDF: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+/// The namespace of `xml:` attributes like `xml:lang`.
+const XML: &[u8] = b"http://www.w3.org/XML/1998/namespace";
+
+/// How deeply elements can be nested before a packet is rejected. Reading the tree and
+/// dropping it both recurse, and real XMP is only a handful of levels deep.
+const MAX_DEPTH: usize = 64;
+
+/// The usual prefixes of common XMP namespaces, so that their properties are keyed the
+/// same whatever prefixes a packet declares for them.
+const PREFIXES: &[(&[u8], &str)] = &[
+    (b"http://purl.org/dc/elements/1.1/", "dc"),
+    (b"http://ns.adobe.com/xap/1.0/", "xmp"),
+    (b"http://ns.adobe.com/xap/1.0/mm/", "xmpMM"),
+    (b"http://ns.adobe.com/xap/1.0/rights/", "xmpRights"),
+    (b"http://ns.adobe.com/xap/1.0/sType/ResourceRef#", "stRef"),
+    (b"http://ns.adobe.com/xap/1.0/sType/ResourceEvent#", "stEvt"),
+    (b"http://ns.adobe.com/pdf/1.3/", "pdf"),
+    (b"http://www.aiim.org/pdfa/ns/id/", "pdfaid"),
+    (b"http://ns.adobe.com/photoshop/1.0/", "photoshop"),
+];
+
+/// The document's XMP packet, from the metadata stream of the document catalog.
+pub(crate) fn find_xmp(raw: &RawPdf) -> Option<String> {
+    let metadata = raw.catalog()?.get(b"Metadata")?.as_reference()?;
+    let data = raw.object(metadata)?.decoded_stream()?;
+    let xml = String::from_utf8(data).ok()?;
+    Some(xml.trim_end().to_owned())
+}
+
+/// A name resolved to its namespace, which is empty for names without one.
+#[derive(Default)]
+struct Name {
+    namespace: Vec<u8>,
+    local: String,
+    /// The name properties are keyed by, like `dc:title`
+    key: String,
+}
+
+impl Name {
+    fn new(namespace: ResolveResult, qualified: &[u8]) -> Name {
+        let qualified = String::from_utf8_lossy(qualified);
+        let (prefix, local) = match qualified.split_once(':') {
+            Some((prefix, local)) => (Some(prefix), local),
+            None => (None, qualified.as_ref()),
+        };
+        let namespace = match namespace {
+            ResolveResult::Bound(namespace) => namespace.into_inner().to_vec(),
+            _ => vec![],
+        };
+        let prefix = PREFIXES
+            .iter()
+            .find(|(uri, _)| *uri == namespace.as_slice())
+            .map(|(_, prefix)| *prefix)
+            .or(prefix);
+        Name {
+            key: match prefix {
+                Some(prefix) => format!("{prefix}:{local}"),
+                None => local.to_owned(),
+            },
+            local: local.to_owned(),
+            namespace,
+        }
+    }
+
+    fn is(&self, namespace: &[u8], local: &str) -> bool {
+        self.namespace == namespace && self.local == local
+    }
+}
+
+/// A minimal XML element, enough to walk the RDF inside an XMP packet.
+#[derive(Default)]
+struct Element {
+    name: Name,
+    attributes: Vec<(Name, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attribute(&self, namespace: &[u8], local: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(name, _)| name.is(namespace, local))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, namespace: &[u8], local: &str) -> Option<&Element> {
+        self.children
+            .iter()
+            .find(|child| child.name.is(namespace, local))
+    }
+
+    /// The `rdf:li` items of an `rdf:Alt`, `rdf:Seq` or `rdf:Bag`.
+    fn items(&self) -> impl Iterator<Item = &Element> {
+        self.children
+            .iter()
+            .filter(|child| child.name.is(RDF, "li"))
+    }
+}
+
+/// Parses an XML document into a tree under an unnamed root element. Mismatched closing
+/// tags are allowed, since XMP packets are often written by hand-rolled serializers, but
+/// elements nested deeper than `MAX_DEPTH` aren't.
+fn parse_xml(xml: &str) -> Option<Element> {
+    let mut reader = NsReader::from_str(xml);
+    reader.check_end_names(false);
+    let mut stack = vec![Element::default()];
+    loop {
+        let (namespace, event) = reader.read_resolved_event().ok()?;
+        let (start, empty) = match event {
+            Event::Start(start) => (start, false),
+            Event::Empty(start) => (start, true),
+            Event::End(_) => {
+                let element = stack.pop()?;
+                stack.last_mut()?.children.push(element);
+                continue;
+            }
+            Event::Text(text) => {
+                stack.last_mut()?.text.push_str(&text.unescape().ok()?);
+                continue;
+            }
+            Event::CData(data) => {
+                let data = data.into_inner();
+                stack
+                    .last_mut()?
+                    .text
+                    .push_str(std::str::from_utf8(&data).ok()?);
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let mut element = Element {
+            name: Name::new(namespace, start.name().as_ref()),
+            ..Default::default()
+        };
+        for attribute in start.attributes().with_checks(false) {
+            let attribute = attribute.ok()?;
+            // namespace declarations are RDF/XML syntax rather than XMP properties
+            if attribute.key.as_namespace_binding().is_some() {
+                continue;
+            }
+            let (namespace, _) = reader.resolve_attribute(attribute.key);
+            let name = Name::new(namespace, attribute.key.as_ref());
+            let value = attribute.unescape_value().ok()?.into_owned();
+            element.attributes.push((name, value));
+        }
+        if empty {
+            stack.last_mut()?.children.push(element);
+        } else if stack.len() > MAX_DEPTH {
+            return None;
+        } else {
+            stack.push(element);
+        }
+    }
+    // close any elements left open by a truncated packet
+    while stack.len() > 1 {
+        let element = stack.pop()?;
+        stack.last_mut()?.children.push(element);
+    }
+    stack.pop()
+}
+
+/// Whether an attribute is RDF/XML syntax rather than an XMP property.
+fn is_syntax_attribute(name: &Name) -> bool {
+    name.namespace == RDF || name.namespace == XML
+}
+
+/// The properties of an `rdf:Description` (or of a struct), from both its attributes and
+/// its child elements.
+fn properties(element: &Element) -> Map<String, Value> {
+    let mut properties = Map::new();
+    for (name, value) in &element.attributes {
+        if !is_syntax_attribute(name) {
+            properties.insert(name.key.clone(), Value::String(value.clone()));
+        }
+    }
+    for child in &element.children {
+        properties.insert(child.name.key.clone(), property_value(child));
+    }
+    properties
+}
+
+/// The JSON value of an XMP property element.
+fn property_value(element: &Element) -> Value {
+    if let Some(resource) = element.attribute(RDF, "resource") {
+        return Value::String(resource.to_owned());
+    }
+    if let Some(alt) = element.child(RDF, "Alt") {
+        // language alternatives collapse to the default language
+        let default = alt
+            .items()
+            .find(|li| li.attribute(XML, "lang") == Some("x-default"));
+        return default
+            .or_else(|| alt.items().next())
+            .map(property_value)
+            .unwrap_or(Value::Null);
+    }
+    if let Some(array) = element
+        .child(RDF, "Seq")
+        .or_else(|| element.child(RDF, "Bag"))
+    {
+        return Value::Array(array.items().map(property_value).collect());
+    }
+    if let Some(description) = element.child(RDF, "Description") {
+        return Value::Object(properties(description));
+    }
+    let has_fields = element
+        .attributes
+        .iter()
+        .any(|(name, _)| !is_syntax_attribute(name));
+    if element.attribute(RDF, "parseType") == Some("Resource")
+        || !element.children.is_empty()
+        || has_fields
+    {
+        return Value::Object(properties(element));
+    }
+    Value::String(element.text.clone())
+}
+
+/// Flattens struct properties into `outer/inner` keys, leaving arrays as they are.
+fn flatten(prefix: &str, properties: Map<String, Value>, result: &mut Map<String, Value>) {
+    for (name, value) in properties {
+        let key = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        match value {
+            Value::Object(fields) => flatten(&key, fields, result),
+            value => {
+                result.insert(key, value);
+            }
+        }
+    }
+}
+
+fn descriptions<'a>(element: &'a Element, found: &mut Vec<&'a Element>) {
+    for child in &element.children {
+        if child.name.is(RDF, "Description") {
+            found.push(child);
+        } else {
+            descriptions(child, found);
+        }
+    }
+}
+
+/// Converts an XMP packet into a flat JSON object keyed by qualified property names,
+/// like `{"dc:title": "...", "dc:creator": ["..."], "xmpMM:DerivedFrom/stRef:documentID": "..."}`.
+fn xmp_json(xml: &str) -> Option<Value> {
+    let root = parse_xml(xml)?;
+    let mut found = vec![];
+    descriptions(&root, &mut found);
+    let mut result = Map::new();
+    for description in found {
+        flatten("", properties(description), &mut result);
+    }
+    Some(Value::Object(result))
+}
+
+/// The XMP packet of the PDF in the arguments of `pdf_xmp(pdf [, password])` or
+/// `pdf_xmp_json(pdf [, password])`.
+fn document_xmp(values: &[*mut sqlite3_value], function: &str) -> Result<Option<String>> {
+    if values.is_empty() || values.len() > 2 {
+        return Err(Error::new_message(format!(
+            "{function}() takes a PDF and an optional password"
+        )));
+    }
+    let password = match value_arg(values, 1) {
+        Some(value) => Some(api::value_text(value)?),
+        None => None,
+    };
+    let document = load_pdf(pdfium(), api::value_blob(&values[0]), password, function)?;
+    Ok(find_xmp(&RawPdf::from_document(&document)?))
+}
+
+/// pdf_xmp(pdf [, password])
+pub fn pdf_xmp(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    match document_xmp(values, "pdf_xmp")? {
+        Some(xmp) => api::result_text(context, xmp)?,
+        None => api::result_null(context),
+    }
+    Ok(())
+}
+
+/// pdf_xmp_json(pdf [, password])
+pub fn pdf_xmp_json(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    match document_xmp(values, "pdf_xmp_json")? {
+        Some(xmp) => {
+            let json = xmp_json(&xmp).ok_or_else(|| {
+                Error::new_message(
+                    "PDF's XMP metadata is not well-formed XML or is nested too deeply",
+                )
+            })?;
+            api::result_json(context, json)?;
+        }
+        None => api::result_null(context),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flattens_properties() {
+        let xml = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:pdf="http://ns.adobe.com/pdf/1.3/" pdf:Producer="pdfTeX"
+        xmlns:xmpMM="http://ns.adobe.com/xap/1.0/mm/"
+        xmlns:stRef="http://ns.adobe.com/xap/1.0/sType/ResourceRef#">
+      <xmpMM:DerivedFrom rdf:parseType="Resource">
+        <stRef:documentID>uuid:1</stRef:documentID>
+      </xmpMM:DerivedFrom>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+        assert_eq!(
+            xmp_json(xml).unwrap(),
+            json!({"pdf:Producer": "pdfTeX", "xmpMM:DerivedFrom/stRef:documentID": "uuid:1"})
+        );
+    }
+
+    #[test]
+    fn reads_lists() {
+        let xml = r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <rdf:Description rdf:about="">
+    <dc:title><rdf:Alt>
+      <rdf:li xml:lang="de">Titel</rdf:li>
+      <rdf:li xml:lang="x-default">Title</rdf:li>
+    </rdf:Alt></dc:title>
+    <dc:creator><rdf:Seq><rdf:li>Ada</rdf:li><rdf:li>Grace</rdf:li></rdf:Seq></dc:creator>
+    <dc:subject><rdf:Bag><rdf:li>pdf</rdf:li></rdf:Bag></dc:subject>
+    <dc:rights><rdf:Alt><rdf:li xml:lang="en">CC-BY</rdf:li></rdf:Alt></dc:rights>
+  </rdf:Description>
+</rdf:RDF>"#;
+        assert_eq!(
+            xmp_json(xml).unwrap(),
+            json!({
+                "dc:title": "Title",
+                "dc:creator": ["Ada", "Grace"],
+                "dc:subject": ["pdf"],
+                "dc:rights": "CC-BY",
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_namespaces() {
+        // the usual prefixes are used whatever the packet calls its namespaces
+        let xml = r#"<r:RDF xmlns:r="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <r:Description xmlns:purl="http://purl.org/dc/elements/1.1/" xmlns:my="urn:my">
+    <purl:format>application/pdf</purl:format>
+    <my:rdf>not syntax</my:rdf>
+    <rdf:li>undeclared prefix</rdf:li>
+  </r:Description>
+</r:RDF>"#;
+        assert_eq!(
+            xmp_json(xml).unwrap(),
+            json!({
+                "dc:format": "application/pdf",
+                "my:rdf": "not syntax",
+                "rdf:li": "undeclared prefix",
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_text() {
+        let xml = r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <rdf:Description dc:source="a &amp; b &#x263A;">
+    <dc:description>1 &lt; 2 &#38; <![CDATA[<b>bold</b>]]></dc:description>
+  </rdf:Description>"#;
+        assert_eq!(
+            xmp_json(xml).unwrap(),
+            json!({"dc:source": "a & b \u{263A}", "dc:description": "1 < 2 & <b>bold</b>"})
+        );
+        assert!(xmp_json("<rdf:RDF>&unknown;</rdf:RDF>").is_none());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = |depth| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(xmp_json(&nested(MAX_DEPTH)).is_some());
+        assert!(xmp_json(&nested(MAX_DEPTH + 1)).is_none());
+        // unclosed elements count too
+        assert!(xmp_json(&"<a>".repeat(1_000_000)).is_none());
+    }
+
+    #[test]
+    fn finds_the_catalog_metadata() {
+        let raw = RawPdf::new(
+            &b"1 0 obj << /Type /Catalog /Pages 2 0 R /Metadata 4 0 R >> endobj
+3 0 obj << /Type /Metadata /Subtype /XML /Length 7 >> stream
+<old/>
+endstream endobj
+4 0 obj << /Type /Metadata /Subtype /XML /Length 7 >> stream
+<new/>
+endstream endobj
+trailer << /Root 1 0 R >>
+"[..],
+        );
+        assert_eq!(find_xmp(&raw).unwrap(), "<new/>");
+    }
+}
@@ -10,6 +10,30 @@ from pdf_pages(
   readfile('pdf_commenting_new.pdf')
 );
 
+select
+  rowid,
+  rotation,
+  orientation,
+  media_box,
+  crop_box,
+  bleed_box,
+  trim_box,
+  art_box,
+  has_transparency,
+  object_count,
+  annotation_count
+from pdf_pages(
+  readfile('signed_taranto_statement_of_facts_complaint_final_redacted_0.pdf')
+)
+where orientation is not 'portrait'
+  or abs(width - 612) > 2
+  or abs(height - 792) > 2;
+
+select rowid, rotation, orientation, width, height
+from pdf_pages(
+  pdf_rotate_pages(readfile('pdf_commenting_new.pdf'), '1', 90)
+);
+
 
 select
   pdf_pages.rowid,